@@ -0,0 +1,41 @@
+use mosaic_server::{LmdbStore, check_store};
+
+use std::path::PathBuf;
+
+// Usage: fsck [DATA_DIR] [QUARANTINE_DIR]
+//
+// Stop the server first. With a quarantine directory, records that fail
+// verification are moved there and deleted from the store.
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let mut args = std::env::args().skip(1);
+
+    // Storage directory: default to ./mosaic-data, allow override via MOSAIC_DATA_DIR
+    let data_dir = args.next().unwrap_or_else(|| {
+        std::env::var("MOSAIC_DATA_DIR").unwrap_or_else(|_| "./mosaic-data".to_string())
+    });
+    let quarantine_dir = args.next().map(PathBuf::from);
+
+    let store = LmdbStore::open(&data_dir, 16)?;
+    let report = check_store(&store, quarantine_dir.as_deref())?;
+
+    for problem in &report.problems {
+        println!("{problem}");
+    }
+    println!(
+        "checked {} records{}, {} problems, {} quarantined",
+        report.records_checked,
+        if report.indexes_checked {
+            " and their indexes"
+        } else {
+            ""
+        },
+        report.problems.len(),
+        report.quarantined
+    );
+
+    if !report.is_clean() && report.quarantined == 0 {
+        std::process::exit(1);
+    }
+
+    Ok(())
+}
@@ -1,19 +1,10 @@
 use mosaic_core::{PublicKey, SecretKey};
-use mosaic_net::{Approval, Approver};
-use mosaic_server::{LmdbStore, Logger, Server, ServerConfig};
+use mosaic_server::{CidrApprover, CidrRules, LmdbStore, Logger, Server, ServerConfig};
 use tokio::signal::unix::{SignalKind, signal};
 
 use std::net::SocketAddr;
 use std::sync::Arc;
 
-pub struct Denier;
-
-impl Approver for Denier {
-    fn is_client_allowed(&self, _: SocketAddr) -> Approval {
-        Approval::Approve
-    }
-}
-
 pub struct Log;
 
 impl Logger for Log {
@@ -43,6 +34,23 @@ impl Logger for Log {
             eprintln!("{socket_addr}: anonymous, {e}");
         }
     }
+
+    fn log_server_error(&self, e: mosaic_server::Error) {
+        eprintln!("server: {e}");
+    }
+
+    fn log_retention(&self, report: &mosaic_server::RetentionReport) {
+        eprintln!(
+            "retention: removed {} of {} records ({} deferred)",
+            report.removed.len(),
+            report.examined,
+            report.deferred
+        );
+    }
+
+    fn log_backup(&self, path: &std::path::Path) {
+        eprintln!("backup written to {}", path.display());
+    }
 }
 
 #[tokio::main]
@@ -56,23 +64,48 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     let server_socket: SocketAddr = server_addr.parse()?;
     println!("SERVER ENDPOINT IS {}", server_socket);
 
-    let denier = Denier;
+    // Client address rules: allow everyone unless MOSAIC_APPROVER_RULES names a
+    // file of `allow <cidr>` / `deny <cidr>` lines, which SIGHUP reloads
+    let approver = match std::env::var("MOSAIC_APPROVER_RULES") {
+        Ok(path) => CidrApprover::from_file(path)?,
+        Err(_) => CidrApprover::new(CidrRules::default()),
+    };
     let logger = Log;
 
     // Storage directory: default to ./mosaic-data, allow override via MOSAIC_DATA_DIR
     let data_dir = std::env::var("MOSAIC_DATA_DIR").unwrap_or_else(|_| "./mosaic-data".to_string());
-    let store = Arc::new(LmdbStore::open(&data_dir, 4)?);
+    // Start with a 1 GB map and let it grow on demand up to 16 GB
+    let store = Arc::new(LmdbStore::open_growable(&data_dir, 1, 16)?);
 
     let server = Server::new(ServerConfig {
         secret_key,
         socket_addr: server_socket,
-        approver: denier,
+        approver,
         logger,
         store,
+        retention: None,
+        quotas: None,
+        duplicate_filter: None,
+        backups: None,
+        rate_limits: Some(Default::default()),
+        connection_limits: Some(Default::default()),
+        timeouts: Default::default(),
+        peer_authorizer: None,
+        proof_of_work: None,
+        blocklist: Some(format!("{data_dir}/blocklist").into()),
+        moderation: None,
+        read_access: None,
+        read_members: None,
     })?;
 
     let mut interrupt_signal = signal(SignalKind::interrupt())?;
     let mut quit_signal = signal(SignalKind::quit())?;
+    let mut hangup_signal = signal(SignalKind::hangup())?;
+
+    // Started once: a fresh `run()` per signal would restart the accept loop
+    // and its background tasks.
+    let run = server.run();
+    tokio::pin!(run);
 
     loop {
         tokio::select! {
@@ -84,7 +117,13 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
                 eprintln!("SIGQUIT");
                 server.trigger_shut_down(0);
             },
-            r = server.run() => {
+            v = hangup_signal.recv() => if v.is_some() {
+                match server.approver().reload() {
+                    Ok(()) => eprintln!("SIGHUP: approver rules reloaded"),
+                    Err(e) => eprintln!("SIGHUP: approver rules not reloaded: {e}"),
+                }
+            },
+            r = &mut run => {
                 if let Err(e) = r {
                     eprintln!("{e}");
                 }
@@ -0,0 +1,223 @@
+use std::collections::HashSet;
+use std::fmt;
+use std::sync::{PoisonError, RwLock};
+
+use mosaic_core::{Kind, PublicKey, Record, RecordFlags, ResultCode, TagType};
+
+/// Which records are private, and who may read them.
+///
+/// A record is restricted if its kind is listed, it has any of the flags, or
+/// it carries a recipient tag. A restricted record is only served to a
+/// connection authenticated as its author or as one of its recipients; to
+/// everyone else it is reported as not found, so its existence does not leak.
+#[derive(Debug, Clone, Default)]
+pub struct ReadAccessPolicy {
+    /// Records of these kinds are restricted
+    pub restricted_kinds: HashSet<Kind>,
+
+    /// Records with any of these flags set are restricted
+    pub restricted_flags: RecordFlags,
+
+    /// Tags of this type name a recipient: their data starts with the
+    /// recipient's 32-byte public key. Records carrying one are restricted.
+    pub recipient_tag_type: Option<TagType>,
+}
+
+impl ReadAccessPolicy {
+    /// Whether `record` is only served to its author and recipients
+    #[must_use]
+    pub fn is_restricted(&self, record: &Record) -> bool {
+        self.restricted_kinds.contains(&record.kind())
+            || record.flags().intersects(self.restricted_flags)
+            || self.recipients(record).next().is_some()
+    }
+
+    /// Whether a connection authenticated as `peer` may read `record`
+    #[must_use]
+    pub fn may_read(&self, record: &Record, peer: Option<PublicKey>) -> bool {
+        if !self.is_restricted(record) {
+            return true;
+        }
+        let Some(peer) = peer else {
+            return false;
+        };
+        record.author_public_key() == peer
+            || self
+                .recipients(record)
+                .any(|recipient| recipient == peer.as_bytes())
+    }
+
+    /// The recipient keys named in `record`, as bytes
+    fn recipients<'a>(&self, record: &'a Record) -> impl Iterator<Item = &'a [u8]> {
+        let tag_type = self.recipient_tag_type;
+        record
+            .tag_set()
+            .iter()
+            .filter(move |tag| Some(tag.get_type()) == tag_type)
+            .filter_map(|tag| tag.data_bytes().get(..32))
+    }
+}
+
+/// The members of a private server: only connections authenticated as one
+/// of these keys may read. HELLO and submissions are unaffected.
+///
+/// Members can be added and removed while the server runs.
+pub struct ReadMembers {
+    members: RwLock<HashSet<PublicKey>>,
+}
+
+impl ReadMembers {
+    /// Result code of the `QueryClosed` answering reads from non-members
+    pub const RESULT_CODE: ResultCode = ResultCode::RequiresAuthentication;
+
+    /// Let only `members` read
+    pub fn new(members: impl IntoIterator<Item = PublicKey>) -> ReadMembers {
+        ReadMembers {
+            members: RwLock::new(members.into_iter().collect()),
+        }
+    }
+
+    /// Whether a connection authenticated as `peer` may read
+    pub fn may_read(&self, peer: Option<PublicKey>) -> bool {
+        peer.is_some_and(|peer| self.read().contains(&peer))
+    }
+
+    /// Add a member, returning false if it already was one
+    pub fn add(&self, member: PublicKey) -> bool {
+        self.write().insert(member)
+    }
+
+    /// Remove a member, returning false if it was not one
+    pub fn remove(&self, member: &PublicKey) -> bool {
+        self.write().remove(member)
+    }
+
+    /// Replace every member
+    pub fn set(&self, members: impl IntoIterator<Item = PublicKey>) {
+        *self.write() = members.into_iter().collect();
+    }
+
+    /// The current members
+    pub fn members(&self) -> Vec<PublicKey> {
+        self.read().iter().copied().collect()
+    }
+
+    fn read(&self) -> std::sync::RwLockReadGuard<'_, HashSet<PublicKey>> {
+        self.members.read().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn write(&self) -> std::sync::RwLockWriteGuard<'_, HashSet<PublicKey>> {
+        self.members.write().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+impl Clone for ReadMembers {
+    fn clone(&self) -> ReadMembers {
+        ReadMembers::new(self.members())
+    }
+}
+
+impl fmt::Debug for ReadMembers {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ReadMembers")
+            .field("members", &self.read().len())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use mosaic_core::{
+        OwnedRecord, OwnedTag, OwnedTagSet, RecordAddressData, RecordParts, RecordSigningData,
+        SecretKey, Timestamp,
+    };
+
+    use crate::test_support::build_record_with;
+
+    /// A chat message by `author` with the given flags and tags
+    fn build_tagged(author: &SecretKey, flags: RecordFlags, tags: &[OwnedTag]) -> OwnedRecord {
+        let mut tag_set = OwnedTagSet::new();
+        for tag in tags {
+            tag_set.add_tag(tag);
+        }
+        OwnedRecord::new(&RecordParts {
+            signing_data: RecordSigningData::SecretKey(author.clone()),
+            address_data: RecordAddressData::Random(author.public(), Kind::CHAT_MESSAGE),
+            timestamp: Timestamp::now().unwrap(),
+            flags,
+            tag_set: &tag_set,
+            payload: b"tagged",
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn restricted_kinds_are_only_readable_by_their_author() {
+        let policy = ReadAccessPolicy {
+            restricted_kinds: [Kind::KEY_SCHEDULE].into_iter().collect(),
+            ..Default::default()
+        };
+        let author = SecretKey::generate();
+        let now = Timestamp::now().unwrap();
+        let private = build_record_with(&author, Kind::KEY_SCHEDULE, now, b"private");
+        let public = build_record_with(&author, Kind::CHAT_MESSAGE, now, b"public");
+        let stranger = SecretKey::generate().public();
+
+        assert!(policy.is_restricted(private.as_ref()));
+        assert!(policy.may_read(private.as_ref(), Some(author.public())));
+        assert!(!policy.may_read(private.as_ref(), Some(stranger)));
+        assert!(!policy.may_read(private.as_ref(), None));
+
+        assert!(!policy.is_restricted(public.as_ref()));
+        assert!(policy.may_read(public.as_ref(), None));
+    }
+
+    #[test]
+    fn recipients_may_read_records_naming_them() {
+        let recipient_tag = TagType::NOTIFY_PUBLIC_KEY;
+        let policy = ReadAccessPolicy {
+            recipient_tag_type: Some(recipient_tag),
+            ..Default::default()
+        };
+        let author = SecretKey::generate();
+        let recipient = SecretKey::generate().public();
+        let stranger = SecretKey::generate().public();
+
+        let tag = OwnedTag::new(recipient_tag, recipient.as_bytes()).unwrap();
+        let addressed = build_tagged(&author, RecordFlags::empty(), &[tag]);
+        assert!(policy.is_restricted(addressed.as_ref()));
+        assert!(policy.may_read(addressed.as_ref(), Some(recipient)));
+        assert!(policy.may_read(addressed.as_ref(), Some(author.public())));
+        assert!(!policy.may_read(addressed.as_ref(), Some(stranger)));
+        assert!(!policy.may_read(addressed.as_ref(), None));
+
+        // A tag too short to hold a key names no recipient
+        let short = OwnedTag::new(recipient_tag, &recipient.as_bytes()[..31]).unwrap();
+        let malformed = build_tagged(&author, RecordFlags::empty(), &[short]);
+        assert!(!policy.is_restricted(malformed.as_ref()));
+        assert!(policy.may_read(malformed.as_ref(), None));
+    }
+
+    #[test]
+    fn restricted_flags_are_only_readable_by_their_author() {
+        let policy = ReadAccessPolicy {
+            restricted_flags: RecordFlags::EPHEMERAL,
+            ..Default::default()
+        };
+        let author = SecretKey::generate();
+        let flagged = build_tagged(&author, RecordFlags::EPHEMERAL, &[]);
+        let plain = build_tagged(&author, RecordFlags::empty(), &[]);
+
+        assert!(policy.is_restricted(flagged.as_ref()));
+        assert!(policy.may_read(flagged.as_ref(), Some(author.public())));
+        assert!(!policy.may_read(flagged.as_ref(), Some(SecretKey::generate().public())));
+
+        assert!(!policy.is_restricted(plain.as_ref()));
+        assert!(policy.may_read(plain.as_ref(), None));
+
+        // With no flags restricted, no flagged record is
+        assert!(!ReadAccessPolicy::default().is_restricted(flagged.as_ref()));
+    }
+}
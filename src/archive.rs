@@ -0,0 +1,266 @@
+use std::io::{ErrorKind, Read, Seek, SeekFrom, Write};
+
+use mosaic_core::OwnedRecord;
+
+use crate::{Error, InnerError, PutResult, ScanOrder, Store};
+
+/// Magic bytes at the start of every archive
+const MAGIC: &[u8; 8] = b"MOSAICAR";
+
+/// Archive format version written by `export_archive`
+const VERSION: u16 = 1;
+
+/// Header: magic, big-endian version, six reserved zero bytes
+const HEADER_LEN: usize = 16;
+
+/// Records larger than this are not produced by any valid Mosaic client
+const MAX_RECORD_LEN: usize = 1 << 20;
+
+/// Records read from the store per scan
+const EXPORT_BATCH: usize = 256;
+
+/// Outcome of `export_archive`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ExportReport {
+    /// Number of records written
+    pub records: u64,
+}
+
+/// Outcome of `import_archive`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ImportReport {
+    /// Records newly inserted into the store
+    pub inserted: u64,
+
+    /// Records the store already held
+    pub duplicate: u64,
+
+    /// Records that failed verification and were skipped
+    pub rejected: u64,
+}
+
+/// Write every record in `store` to `writer` as a portable archive.
+///
+/// The archive is a 16 byte header, then each record's raw bytes prefixed by a
+/// big-endian `u32` length, then a zero length marker followed by the record
+/// count (`u64`) and a BLAKE3 hash of everything between header and marker.
+pub fn export_archive<W: Write>(store: &dyn Store, mut writer: W) -> Result<ExportReport, Error> {
+    let mut header = [0; HEADER_LEN];
+    header[..8].copy_from_slice(MAGIC);
+    header[8..10].copy_from_slice(&VERSION.to_be_bytes());
+    writer.write_all(&header)?;
+
+    let mut hasher = blake3::Hasher::new();
+    let mut report = ExportReport::default();
+    let mut after = None;
+    loop {
+        let batch = store.scan(ScanOrder::Insertion, after.as_ref(), EXPORT_BATCH)?;
+        let Some(last) = batch.last() else {
+            break;
+        };
+        after = Some(last.id);
+
+        for entry in &batch {
+            let len = u32::try_from(entry.bytes.len()).map_err(|_| {
+                InnerError::General("record too long to archive".to_owned()).into_err()
+            })?;
+            let len = len.to_be_bytes();
+            hasher.update(&len);
+            hasher.update(&entry.bytes);
+            writer.write_all(&len)?;
+            writer.write_all(&entry.bytes)?;
+            report.records += 1;
+        }
+    }
+
+    writer.write_all(&0_u32.to_be_bytes())?;
+    writer.write_all(&report.records.to_be_bytes())?;
+    writer.write_all(hasher.finalize().as_bytes())?;
+    writer.flush()?;
+
+    Ok(report)
+}
+
+/// Read an archive produced by `export_archive` into `store`.
+///
+/// The whole archive is read once to check its header, record count and
+/// checksum before anything is written, so a truncated or tampered archive
+/// imports nothing. It is then read again from the start and every record is
+/// re-verified before insertion; records that fail are counted as rejected and
+/// skipped. Store failures abort the import. The archive must not change
+/// between the two passes.
+pub fn import_archive<R: Read + Seek>(
+    store: &dyn Store,
+    mut reader: R,
+) -> Result<ImportReport, Error> {
+    let start = reader.stream_position()?;
+    read_records(&mut reader, |_| Ok(()))?;
+
+    reader.seek(SeekFrom::Start(start))?;
+    let mut report = ImportReport::default();
+    read_records(&mut reader, |bytes| {
+        match OwnedRecord::from_vec(bytes) {
+            Ok(record) => match store.put_record(record.as_ref())? {
+                PutResult::Inserted => report.inserted += 1,
+                PutResult::Duplicate => report.duplicate += 1,
+            },
+            Err(_) => report.rejected += 1,
+        }
+        Ok(())
+    })?;
+
+    Ok(report)
+}
+
+/// Read an archive, passing each record's bytes to `each`, and check its
+/// trailer against the records read.
+fn read_records<R, F>(reader: &mut R, mut each: F) -> Result<(), Error>
+where
+    R: Read,
+    F: FnMut(Vec<u8>) -> Result<(), Error>,
+{
+    let mut header = [0; HEADER_LEN];
+    read_exact(reader, &mut header)?;
+    if &header[..8] != MAGIC {
+        return Err(InnerError::General("not a mosaic archive".to_owned()).into_err());
+    }
+    let version = u16::from_be_bytes([header[8], header[9]]);
+    if version != VERSION {
+        return Err(
+            InnerError::General(format!("unsupported mosaic archive version {version}")).into_err(),
+        );
+    }
+    if header[10..].iter().any(|&b| b != 0) {
+        return Err(
+            InnerError::General("mosaic archive header has reserved bytes set".to_owned())
+                .into_err(),
+        );
+    }
+
+    let mut hasher = blake3::Hasher::new();
+    let mut count: u64 = 0;
+    loop {
+        let mut len_bytes = [0; 4];
+        read_exact(reader, &mut len_bytes)?;
+        let len = u32::from_be_bytes(len_bytes) as usize;
+        if len == 0 {
+            break;
+        }
+        if len > MAX_RECORD_LEN {
+            return Err(
+                InnerError::General(format!("archived record length {len} is too long")).into_err(),
+            );
+        }
+
+        let mut bytes = vec![0; len];
+        read_exact(reader, &mut bytes)?;
+        hasher.update(&len_bytes);
+        hasher.update(&bytes);
+        count += 1;
+        each(bytes)?;
+    }
+
+    let mut count_bytes = [0; 8];
+    read_exact(reader, &mut count_bytes)?;
+    let mut checksum = [0; 32];
+    read_exact(reader, &mut checksum)?;
+
+    let expected_count = u64::from_be_bytes(count_bytes);
+    if expected_count != count {
+        return Err(InnerError::General(format!(
+            "mosaic archive lists {expected_count} records but contains {count}"
+        ))
+        .into_err());
+    }
+    if hasher.finalize().as_bytes() != &checksum {
+        return Err(InnerError::General("mosaic archive checksum mismatch".to_owned()).into_err());
+    }
+    Ok(())
+}
+
+fn read_exact<R: Read>(reader: &mut R, buf: &mut [u8]) -> Result<(), Error> {
+    reader.read_exact(buf).map_err(|e| {
+        if e.kind() == ErrorKind::UnexpectedEof {
+            InnerError::General("mosaic archive is truncated".to_owned()).into_err()
+        } else {
+            e.into()
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::io::Cursor;
+
+    use crate::test_support::{InMemoryStore, build_record};
+
+    fn populated_store(count: usize) -> InMemoryStore {
+        let store = InMemoryStore::default();
+        for i in 0..count {
+            let record = build_record(format!("archive record {i}").as_bytes());
+            store.put_record(record.as_ref()).unwrap();
+        }
+        store
+    }
+
+    #[test]
+    fn export_then_import_roundtrips() {
+        let source = populated_store(300);
+        let mut archive = Vec::new();
+        let exported = export_archive(&source, &mut archive).unwrap();
+        assert_eq!(exported.records, 300);
+
+        let target = InMemoryStore::default();
+        let imported = import_archive(&target, Cursor::new(&archive)).unwrap();
+        assert_eq!(
+            imported,
+            ImportReport {
+                inserted: 300,
+                duplicate: 0,
+                rejected: 0
+            }
+        );
+        assert_eq!(target.record_count(), 300);
+
+        let again = import_archive(&target, Cursor::new(&archive)).unwrap();
+        assert_eq!(again.duplicate, 300);
+        assert_eq!(again.inserted, 0);
+    }
+
+    #[test]
+    fn tampered_archive_imports_nothing() {
+        let source = populated_store(2);
+        let mut archive = Vec::new();
+        export_archive(&source, &mut archive).unwrap();
+
+        // Flip a byte inside the first record (just past its length prefix).
+        archive[HEADER_LEN + 4] ^= 0xFF;
+
+        let target = InMemoryStore::default();
+        let err = import_archive(&target, Cursor::new(&archive)).unwrap_err();
+        assert!(err.to_string().contains("checksum"));
+        assert_eq!(target.record_count(), 0);
+    }
+
+    #[test]
+    fn truncated_and_foreign_archives_are_refused() {
+        let source = populated_store(1);
+        let mut archive = Vec::new();
+        export_archive(&source, &mut archive).unwrap();
+
+        let target = InMemoryStore::default();
+        let truncated = &archive[..archive.len() - 1];
+        assert!(import_archive(&target, Cursor::new(truncated)).is_err());
+        assert_eq!(target.record_count(), 0);
+
+        let mut reserved = archive.clone();
+        reserved[HEADER_LEN - 1] = 1;
+        let err = import_archive(&target, Cursor::new(&reserved)).unwrap_err();
+        assert!(err.to_string().contains("reserved"));
+
+        assert!(import_archive(&target, Cursor::new(b"NOTANARCHIVE0000")).is_err());
+        assert_eq!(target.record_count(), 0);
+    }
+}
@@ -0,0 +1,95 @@
+use std::net::SocketAddr;
+
+use mosaic_core::{PublicKey, ResultCode};
+
+/// What an authorized connection may do
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Role {
+    /// May fetch and query records, but not submit them
+    ReadOnly,
+
+    /// May fetch, query and submit records
+    ReadWrite,
+
+    /// May fetch, query and submit records, and is trusted by the server:
+    /// exempt from rate limits and proof of work, and may read a private
+    /// server without being a member. Author quotas still apply.
+    Admin,
+}
+
+impl Role {
+    /// Result code of requests refused because of the connection's role
+    pub const RESULT_CODE: ResultCode = ResultCode::Unauthorized;
+
+    /// Whether the connection may submit records
+    #[must_use]
+    pub fn can_write(&self) -> bool {
+        *self >= Role::ReadWrite
+    }
+
+    /// Whether the connection is exempt from rate limits, proof of work and
+    /// private-server membership
+    #[must_use]
+    pub fn is_admin(&self) -> bool {
+        *self == Role::Admin
+    }
+}
+
+/// The decision of a `PeerAuthorizer` about a new connection
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Authorization {
+    /// Serve the connection with this role
+    Grant(Role),
+
+    /// Close the connection with this result code
+    Reject(ResultCode),
+}
+
+/// Decides, once a client has completed the QUIC handshake, whether to serve
+/// it and with which role.
+///
+/// Unlike `mosaic_net::Approver`, which only sees the address, this sees the
+/// public key the client authenticated with, or `None` if it connected
+/// anonymously. Closures of the same signature implement it.
+pub trait PeerAuthorizer: Send + Sync {
+    /// Authorize a client connecting from `socket_addr` as `peer`
+    fn authorize(&self, peer: Option<PublicKey>, socket_addr: SocketAddr) -> Authorization;
+}
+
+impl<F> PeerAuthorizer for F
+where
+    F: Fn(Option<PublicKey>, SocketAddr) -> Authorization + Send + Sync,
+{
+    fn authorize(&self, peer: Option<PublicKey>, socket_addr: SocketAddr) -> Authorization {
+        self(peer, socket_addr)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use mosaic_core::SecretKey;
+
+    #[test]
+    fn closures_authorize_by_key() {
+        let admin = SecretKey::generate().public();
+        let authorizer = move |peer: Option<PublicKey>, _: SocketAddr| match peer {
+            Some(key) if key == admin => Authorization::Grant(Role::Admin),
+            Some(_) => Authorization::Grant(Role::ReadWrite),
+            None => Authorization::Grant(Role::ReadOnly),
+        };
+        let authorizer: &dyn PeerAuthorizer = &authorizer;
+        let addr: SocketAddr = "192.0.2.1:4000".parse().unwrap();
+
+        assert_eq!(
+            authorizer.authorize(Some(admin), addr),
+            Authorization::Grant(Role::Admin)
+        );
+        let Authorization::Grant(anonymous) = authorizer.authorize(None, addr) else {
+            panic!("anonymous peers should be granted a role");
+        };
+        assert!(!anonymous.can_write());
+        assert!(Role::ReadWrite.can_write() && !Role::ReadWrite.is_admin());
+    }
+}
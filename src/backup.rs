@@ -0,0 +1,197 @@
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use tokio::sync::SetOnce;
+
+use crate::{Error, Logger, Store};
+
+const SNAPSHOT_PREFIX: &str = "backup-";
+const PARTIAL_SUFFIX: &str = ".partial";
+
+/// When and where the server takes periodic backups of its store
+#[derive(Debug, Clone)]
+pub struct BackupPolicy {
+    /// Directory holding one subdirectory per snapshot
+    pub dir: PathBuf,
+
+    /// How often to take a snapshot
+    pub interval: Duration,
+
+    /// Number of most recent snapshots to keep; older ones are deleted
+    pub keep: usize,
+}
+
+impl Default for BackupPolicy {
+    fn default() -> BackupPolicy {
+        BackupPolicy {
+            dir: PathBuf::from("./mosaic-backups"),
+            interval: Duration::from_secs(24 * 60 * 60),
+            keep: 7,
+        }
+    }
+}
+
+/// Take a snapshot of `store` into a new `backup-<millis>` directory under
+/// `dir`, then delete all but the `keep` newest snapshots there.
+///
+/// The snapshot is written under a `.partial` name and renamed once complete,
+/// so an interrupted backup is never mistaken for a good one. Returns the path
+/// of the new snapshot.
+pub fn backup_rotated(store: &dyn Store, dir: &Path, keep: usize) -> Result<PathBuf, Error> {
+    std::fs::create_dir_all(dir)?;
+
+    let millis = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis();
+    let name = format!("{SNAPSHOT_PREFIX}{millis}");
+    let partial = dir.join(format!("{name}{PARTIAL_SUFFIX}"));
+    let complete = dir.join(name);
+
+    if partial.exists() {
+        std::fs::remove_dir_all(&partial)?;
+    }
+    if let Err(e) = store.backup_to(&partial) {
+        let _ = std::fs::remove_dir_all(&partial);
+        return Err(e);
+    }
+    std::fs::rename(&partial, &complete)?;
+
+    prune_snapshots(dir, keep.max(1))?;
+    Ok(complete)
+}
+
+/// Delete leftover partial snapshots and all but the `keep` newest complete ones
+fn prune_snapshots(dir: &Path, keep: usize) -> Result<(), Error> {
+    let mut snapshots = Vec::new();
+    for entry in std::fs::read_dir(dir)? {
+        let entry = entry?;
+        let file_name = entry.file_name();
+        let Some(name) = file_name.to_str() else {
+            continue;
+        };
+        let Some(stamp) = name.strip_prefix(SNAPSHOT_PREFIX) else {
+            continue;
+        };
+        if stamp.ends_with(PARTIAL_SUFFIX) {
+            std::fs::remove_dir_all(entry.path())?;
+        } else if let Ok(millis) = stamp.parse::<u128>() {
+            snapshots.push((millis, entry.path()));
+        }
+    }
+
+    snapshots.sort_unstable_by_key(|(millis, _)| std::cmp::Reverse(*millis));
+    for (_, path) in snapshots.into_iter().skip(keep) {
+        std::fs::remove_dir_all(path)?;
+    }
+    Ok(())
+}
+
+/// Take a rotated backup every `policy.interval` until shutdown starts.
+pub(crate) async fn run_backups<L: Logger + 'static>(
+    store: Arc<dyn Store>,
+    policy: Arc<BackupPolicy>,
+    logger: Arc<L>,
+    shutting_down: Arc<SetOnce<u32>>,
+) {
+    loop {
+        tokio::select! {
+            _ = tokio::time::sleep(policy.interval) => {},
+            _ = shutting_down.wait() => break,
+        }
+
+        let store2 = store.clone();
+        let policy2 = policy.clone();
+        let pass = tokio::task::spawn_blocking(move || {
+            backup_rotated(&*store2, &policy2.dir, policy2.keep)
+        })
+        .await;
+
+        match pass {
+            Ok(Ok(path)) => logger.log_backup(&path),
+            Ok(Err(e)) => logger.log_server_error(e),
+            Err(e) => logger.log_server_error(e.into()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::test_support::InMemoryStore;
+    use crate::{PutResult, RecordTotals, ScanEntry, ScanOrder, StoreStats};
+
+    /// Writes a marker file as its "snapshot"
+    #[derive(Default)]
+    struct MarkerStore(InMemoryStore);
+
+    impl Store for MarkerStore {
+        fn put_record(&self, record: &mosaic_core::Record) -> Result<PutResult, Error> {
+            self.0.put_record(record)
+        }
+
+        fn has_record(&self, reference: &mosaic_core::Reference) -> Result<bool, Error> {
+            self.0.has_record(reference)
+        }
+
+        fn get_record(
+            &self,
+            reference: &mosaic_core::Reference,
+        ) -> Result<Option<mosaic_core::OwnedRecord>, Error> {
+            self.0.get_record(reference)
+        }
+
+        fn stats(&self) -> Result<StoreStats, Error> {
+            self.0.stats()
+        }
+
+        fn author_stats(&self, author: &mosaic_core::PublicKey) -> Result<RecordTotals, Error> {
+            self.0.author_stats(author)
+        }
+
+        fn delete_record(&self, reference: &mosaic_core::Reference) -> Result<bool, Error> {
+            self.0.delete_record(reference)
+        }
+
+        fn scan(
+            &self,
+            order: ScanOrder,
+            after: Option<&mosaic_core::Id>,
+            limit: usize,
+        ) -> Result<Vec<ScanEntry>, Error> {
+            self.0.scan(order, after, limit)
+        }
+
+        fn backup_to(&self, path: &Path) -> Result<(), Error> {
+            std::fs::create_dir_all(path)?;
+            std::fs::write(path.join("snapshot"), b"ok")?;
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn only_the_newest_snapshots_are_kept() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::create_dir(dir.path().join("backup-1.partial")).unwrap();
+        std::fs::create_dir(dir.path().join("unrelated")).unwrap();
+
+        let store = MarkerStore::default();
+        let mut taken = Vec::new();
+        for _ in 0..3 {
+            taken.push(backup_rotated(&store, dir.path(), 2).unwrap());
+            std::thread::sleep(Duration::from_millis(2));
+        }
+
+        assert!(!taken[0].exists());
+        assert!(taken[1].join("snapshot").exists());
+        assert!(taken[2].join("snapshot").exists());
+        assert!(!dir.path().join("backup-1.partial").exists());
+        assert!(dir.path().join("unrelated").exists());
+
+        // Stores without snapshot support leave nothing behind.
+        assert!(backup_rotated(&InMemoryStore::default(), dir.path(), 2).is_err());
+        assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 3);
+    }
+}
@@ -0,0 +1,286 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs::File;
+use std::io::{ErrorKind, Write};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::{PoisonError, RwLock};
+
+use mosaic_core::{Id, PublicKey, Record, ResultCode};
+
+use crate::{Error, InnerError, ScanOrder, Store, hex};
+
+/// Records read from the store per scan while purging
+const PURGE_BATCH: usize = 1024;
+
+/// Something an operator has blocked
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BlockEntry {
+    /// Every record written by this author
+    Author(PublicKey),
+
+    /// The record with this id
+    Record(Id),
+}
+
+impl BlockEntry {
+    fn key(&self) -> BlockKey {
+        match self {
+            BlockEntry::Author(author) => BlockKey::Author(*author.as_bytes()),
+            BlockEntry::Record(id) => BlockKey::Record(*id.as_bytes()),
+        }
+    }
+
+    /// Whether this entry blocks `record`
+    #[must_use]
+    pub fn blocks(&self, record: &Record) -> bool {
+        match self {
+            BlockEntry::Author(author) => record.author_public_key() == *author,
+            BlockEntry::Record(id) => record.id() == *id,
+        }
+    }
+}
+
+// Orders entries (authors first) and looks them up by their bytes
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum BlockKey {
+    Author([u8; 32]),
+    Record([u8; 48]),
+}
+
+impl fmt::Display for BlockEntry {
+    /// The line stored in the blocklist file: `author <hex>` or `record <hex>`
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BlockEntry::Author(author) => write!(f, "author {}", hex::encode(author.as_bytes())),
+            BlockEntry::Record(id) => write!(f, "record {}", hex::encode(id.as_bytes())),
+        }
+    }
+}
+
+impl FromStr for BlockEntry {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<BlockEntry, Error> {
+        let invalid = || InnerError::General(format!("invalid blocklist entry {s:?}")).into_err();
+        let (kind, text) = s.trim().split_once(' ').ok_or_else(invalid)?;
+        match kind {
+            "author" => {
+                let bytes: [u8; 32] = hex::decode(text.trim()).ok_or_else(invalid)?;
+                Ok(BlockEntry::Author(PublicKey::from_bytes(&bytes)?))
+            }
+            "record" => {
+                let bytes: [u8; 48] = hex::decode(text.trim()).ok_or_else(invalid)?;
+                Ok(BlockEntry::Record(Id::from_bytes(&bytes)?))
+            }
+            _ => Err(invalid()),
+        }
+    }
+}
+
+/// Authors and records an operator has blocked, kept in a file.
+///
+/// Submissions of blocked records are refused and stored ones are no longer
+/// served; `purge` deletes them. Every change is written back to the file
+/// before it takes effect, by writing a temporary file next to it and renaming
+/// it over the old one.
+#[derive(Debug)]
+pub struct Blocklist {
+    path: PathBuf,
+    entries: RwLock<BTreeMap<BlockKey, BlockEntry>>,
+}
+
+impl Blocklist {
+    /// Result code of submissions refused because they are blocked
+    pub const RESULT_CODE: ResultCode = ResultCode::Unauthorized;
+
+    /// Load the blocklist kept at `path`, starting empty if the file does not
+    /// exist yet. The file holds one entry per line; blank lines and lines
+    /// starting with `#` are ignored.
+    pub fn open(path: impl Into<PathBuf>) -> Result<Blocklist, Error> {
+        let path = path.into();
+        let text = match std::fs::read_to_string(&path) {
+            Ok(text) => text,
+            Err(e) if e.kind() == ErrorKind::NotFound => String::new(),
+            Err(e) => return Err(e.into()),
+        };
+        let mut entries = BTreeMap::new();
+        for line in text.lines() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let entry: BlockEntry = line.parse()?;
+            entries.insert(entry.key(), entry);
+        }
+        Ok(Blocklist {
+            path,
+            entries: RwLock::new(entries),
+        })
+    }
+
+    /// The file the blocklist is kept in
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Add `entry`, returning false if it was already blocked.
+    ///
+    /// The file is rewritten and synced to disk before this returns, with the
+    /// list locked: lookups from connection handlers wait for the disk, so
+    /// changes are best made rarely and from a blocking context.
+    pub fn block(&self, entry: BlockEntry) -> Result<bool, Error> {
+        let mut entries = self.entries.write().unwrap_or_else(PoisonError::into_inner);
+        if entries.contains_key(&entry.key()) {
+            return Ok(false);
+        }
+        let mut updated = entries.clone();
+        updated.insert(entry.key(), entry);
+        self.save(&updated)?;
+        *entries = updated;
+        Ok(true)
+    }
+
+    /// Remove `entry`, returning false if it was not blocked. Blocks on the
+    /// disk like `block`.
+    pub fn unblock(&self, entry: &BlockEntry) -> Result<bool, Error> {
+        let mut entries = self.entries.write().unwrap_or_else(PoisonError::into_inner);
+        if !entries.contains_key(&entry.key()) {
+            return Ok(false);
+        }
+        let mut updated = entries.clone();
+        updated.remove(&entry.key());
+        self.save(&updated)?;
+        *entries = updated;
+        Ok(true)
+    }
+
+    /// Every blocked author and record, authors first
+    pub fn entries(&self) -> Vec<BlockEntry> {
+        self.entries
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .values()
+            .copied()
+            .collect()
+    }
+
+    /// Whether `record` is blocked, by its id or its author
+    pub fn blocks(&self, record: &Record) -> bool {
+        let entries = self.entries.read().unwrap_or_else(PoisonError::into_inner);
+        entries.contains_key(&BlockKey::Record(*record.id().as_bytes()))
+            || entries.contains_key(&BlockKey::Author(*record.author_public_key().as_bytes()))
+    }
+
+    /// Delete every blocked record from `store`, returning their ids
+    pub fn purge(&self, store: &dyn Store) -> Result<Vec<Id>, Error> {
+        let mut doomed = Vec::new();
+        let mut after = None;
+        loop {
+            let batch = store.scan(ScanOrder::Insertion, after.as_ref(), PURGE_BATCH)?;
+            let Some(last) = batch.last() else {
+                break;
+            };
+            after = Some(last.id);
+            doomed.extend(
+                batch
+                    .iter()
+                    .filter(|entry| {
+                        Record::from_bytes(&entry.bytes).is_ok_and(|record| self.blocks(record))
+                    })
+                    .map(|entry| entry.id),
+            );
+        }
+
+        let mut purged = Vec::with_capacity(doomed.len());
+        for id in doomed {
+            if store.delete_record(&id.to_reference())? {
+                purged.push(id);
+            }
+        }
+        Ok(purged)
+    }
+
+    fn save(&self, entries: &BTreeMap<BlockKey, BlockEntry>) -> Result<(), Error> {
+        let mut text = String::from("# Mosaic server blocklist\n");
+        for entry in entries.values() {
+            text.push_str(&entry.to_string());
+            text.push('\n');
+        }
+        let mut partial = self.path.clone().into_os_string();
+        partial.push(".partial");
+        let mut file = File::create(&partial)?;
+        file.write_all(text.as_bytes())?;
+        file.sync_all()?;
+        drop(file);
+        std::fs::rename(&partial, &self.path)?;
+
+        // Make the rename itself durable
+        let dir = match self.path.parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => dir,
+            _ => Path::new("."),
+        };
+        File::open(dir)?.sync_all()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use mosaic_core::{Kind, SecretKey, Timestamp};
+
+    use crate::test_support::{InMemoryStore, build_record, build_record_with};
+
+    #[test]
+    fn entries_survive_reopening() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("blocklist");
+        let author = SecretKey::generate().public();
+        let record = build_record(b"blocked by id");
+
+        let blocklist = Blocklist::open(&path).unwrap();
+        assert!(blocklist.entries().is_empty());
+        assert!(blocklist.block(BlockEntry::Author(author)).unwrap());
+        assert!(blocklist.block(BlockEntry::Record(record.id())).unwrap());
+        assert!(!blocklist.block(BlockEntry::Author(author)).unwrap());
+        assert!(blocklist.blocks(record.as_ref()));
+
+        let reopened = Blocklist::open(&path).unwrap();
+        assert_eq!(reopened.entries(), blocklist.entries());
+        assert!(reopened.unblock(&BlockEntry::Record(record.id())).unwrap());
+        assert!(!reopened.blocks(record.as_ref()));
+        assert_eq!(
+            Blocklist::open(&path).unwrap().entries(),
+            vec![BlockEntry::Author(author)]
+        );
+    }
+
+    #[test]
+    fn purge_deletes_blocked_records_only() {
+        let store = InMemoryStore::default();
+        let spammer = SecretKey::generate();
+        let now = Timestamp::now().unwrap();
+        let spam: Vec<_> = (0..3)
+            .map(|i| build_record_with(&spammer, Kind::KEY_SCHEDULE, now, &[i]))
+            .collect();
+        let unwanted = build_record(b"blocked by id");
+        let kept = build_record(b"kept");
+        for record in spam.iter().chain([&unwanted, &kept]) {
+            store.put_record(record.as_ref()).unwrap();
+        }
+
+        let dir = tempfile::tempdir().unwrap();
+        let blocklist = Blocklist::open(dir.path().join("blocklist")).unwrap();
+        blocklist
+            .block(BlockEntry::Author(spammer.public()))
+            .unwrap();
+        blocklist.block(BlockEntry::Record(unwanted.id())).unwrap();
+
+        let purged = blocklist.purge(&store).unwrap();
+        assert_eq!(purged.len(), 4);
+        assert_eq!(store.record_count(), 1);
+        assert!(store.contains(kept.id().as_bytes()));
+    }
+}
@@ -0,0 +1,366 @@
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Mutex, PoisonError};
+use std::time::{Duration, Instant};
+
+use mosaic_core::{Id, OwnedRecord, PublicKey, Record, Reference};
+
+use crate::{Error, FsckProblem, PutResult, RecordTotals, ScanEntry, ScanOrder, Store, StoreStats};
+
+/// Sizing of a `CachedStore`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CacheConfig {
+    /// Most bytes of records held in the cache
+    pub max_bytes: usize,
+
+    /// Most references remembered as recently not found
+    pub max_negative_entries: usize,
+
+    /// How long a "not found" answer is trusted
+    pub negative_ttl: Duration,
+}
+
+impl Default for CacheConfig {
+    fn default() -> CacheConfig {
+        CacheConfig {
+            max_bytes: 64 * 1024 * 1024,
+            max_negative_entries: 10_000,
+            negative_ttl: Duration::from_secs(5),
+        }
+    }
+}
+
+/// Hit and miss counters of a `CachedStore`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CacheStats {
+    /// Lookups answered with a cached record
+    pub hits: u64,
+
+    /// Lookups answered from the negative cache
+    pub negative_hits: u64,
+
+    /// Lookups that went to the underlying store
+    pub misses: u64,
+
+    /// Bytes of records currently cached
+    pub cached_bytes: usize,
+}
+
+/// A `Store` decorator keeping recently read records in a byte-bounded LRU,
+/// plus a short-lived cache of references that were not found.
+///
+/// Entries are invalidated when records are put or deleted through this
+/// wrapper; writes made directly to the inner store are not seen.
+pub struct CachedStore<S: Store> {
+    inner: S,
+    config: CacheConfig,
+    state: Mutex<CacheState>,
+    hits: AtomicU64,
+    negative_hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+#[derive(Default)]
+struct CacheState {
+    // Keyed by the reference bytes the record was looked up with
+    entries: HashMap<[u8; 48], CacheEntry>,
+    // Last use tick to key, oldest first
+    recency: BTreeMap<u64, [u8; 48]>,
+    tick: u64,
+    bytes: usize,
+    negative: HashMap<[u8; 48], Instant>,
+    negative_order: VecDeque<[u8; 48]>,
+    // Bumped by every invalidation so a lookup that raced with a write does
+    // not cache what it read before the write.
+    generation: u64,
+}
+
+struct CacheEntry {
+    record: OwnedRecord,
+    tick: u64,
+}
+
+impl<S: Store> CachedStore<S> {
+    /// Wrap `inner` with a cache sized by `config`
+    pub fn new(inner: S, config: CacheConfig) -> CachedStore<S> {
+        CachedStore {
+            inner,
+            config,
+            state: Mutex::new(CacheState::default()),
+            hits: AtomicU64::new(0),
+            negative_hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+        }
+    }
+
+    /// The wrapped store
+    pub fn inner(&self) -> &S {
+        &self.inner
+    }
+
+    /// Current hit and miss counters
+    pub fn cache_stats(&self) -> CacheStats {
+        CacheStats {
+            hits: self.hits.load(Ordering::Relaxed),
+            negative_hits: self.negative_hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+            cached_bytes: self.lock().bytes,
+        }
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, CacheState> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Drop everything cached about `record`, under its id and its address
+    fn invalidate(&self, record: &Record) {
+        let mut state = self.lock();
+        state.generation += 1;
+        for key in [
+            *record.id().to_reference().as_bytes(),
+            *record.address().to_reference().as_bytes(),
+        ] {
+            state.remove_entry(&key);
+            state.negative.remove(&key);
+        }
+    }
+}
+
+impl CacheState {
+    fn remove_entry(&mut self, key: &[u8; 48]) {
+        if let Some(entry) = self.entries.remove(key) {
+            self.recency.remove(&entry.tick);
+            self.bytes -= entry.record.as_bytes().len();
+        }
+    }
+
+    fn get(&mut self, key: &[u8; 48]) -> Option<OwnedRecord> {
+        self.tick += 1;
+        let tick = self.tick;
+        let entry = self.entries.get_mut(key)?;
+        self.recency.remove(&entry.tick);
+        self.recency.insert(tick, *key);
+        entry.tick = tick;
+        Some(entry.record.clone())
+    }
+
+    fn insert(&mut self, key: [u8; 48], record: OwnedRecord, max_bytes: usize) {
+        let len = record.as_bytes().len();
+        if len > max_bytes {
+            return;
+        }
+        self.remove_entry(&key);
+        while self.bytes + len > max_bytes {
+            let Some((_, oldest)) = self.recency.pop_first() else {
+                break;
+            };
+            if let Some(entry) = self.entries.remove(&oldest) {
+                self.bytes -= entry.record.as_bytes().len();
+            }
+        }
+        self.tick += 1;
+        self.recency.insert(self.tick, key);
+        self.entries.insert(
+            key,
+            CacheEntry {
+                record,
+                tick: self.tick,
+            },
+        );
+        self.bytes += len;
+    }
+
+    /// True if `key` was recently not found
+    fn is_known_missing(&mut self, key: &[u8; 48], ttl: Duration) -> bool {
+        match self.negative.get(key) {
+            Some(at) if at.elapsed() < ttl => true,
+            Some(_) => {
+                self.negative.remove(key);
+                false
+            }
+            None => false,
+        }
+    }
+
+    fn insert_missing(&mut self, key: [u8; 48], max_entries: usize) {
+        if max_entries == 0 {
+            return;
+        }
+        if self.negative.insert(key, Instant::now()).is_none() {
+            self.negative_order.push_back(key);
+        }
+        // The order queue may hold keys already dropped from the map; skip them.
+        while self.negative.len() > max_entries {
+            let Some(oldest) = self.negative_order.pop_front() else {
+                break;
+            };
+            self.negative.remove(&oldest);
+        }
+        if self.negative_order.len() > 2 * max_entries {
+            let negative = &self.negative;
+            self.negative_order.retain(|key| negative.contains_key(key));
+        }
+    }
+}
+
+impl<S: Store> Store for CachedStore<S> {
+    fn put_record(&self, record: &Record) -> Result<PutResult, Error> {
+        let result = self.inner.put_record(record)?;
+        if result == PutResult::Inserted {
+            self.invalidate(record);
+        }
+        Ok(result)
+    }
+
+    fn has_record(&self, reference: &Reference) -> Result<bool, Error> {
+        {
+            let mut state = self.lock();
+            if state.entries.contains_key(reference.as_bytes()) {
+                self.hits.fetch_add(1, Ordering::Relaxed);
+                return Ok(true);
+            }
+            if state.is_known_missing(reference.as_bytes(), self.config.negative_ttl) {
+                self.negative_hits.fetch_add(1, Ordering::Relaxed);
+                return Ok(false);
+            }
+        }
+        self.misses.fetch_add(1, Ordering::Relaxed);
+        self.inner.has_record(reference)
+    }
+
+    fn get_record(&self, reference: &Reference) -> Result<Option<OwnedRecord>, Error> {
+        let key = *reference.as_bytes();
+        let generation = {
+            let mut state = self.lock();
+            if let Some(record) = state.get(&key) {
+                self.hits.fetch_add(1, Ordering::Relaxed);
+                return Ok(Some(record));
+            }
+            if state.is_known_missing(&key, self.config.negative_ttl) {
+                self.negative_hits.fetch_add(1, Ordering::Relaxed);
+                return Ok(None);
+            }
+            state.generation
+        };
+
+        self.misses.fetch_add(1, Ordering::Relaxed);
+        let found = self.inner.get_record(reference)?;
+
+        let mut state = self.lock();
+        if state.generation == generation {
+            match &found {
+                Some(record) => state.insert(key, record.clone(), self.config.max_bytes),
+                None => state.insert_missing(key, self.config.max_negative_entries),
+            }
+        }
+        Ok(found)
+    }
+
+    fn stats(&self) -> Result<StoreStats, Error> {
+        self.inner.stats()
+    }
+
+    fn author_stats(&self, author: &PublicKey) -> Result<RecordTotals, Error> {
+        self.inner.author_stats(author)
+    }
+
+    fn delete_record(&self, reference: &Reference) -> Result<bool, Error> {
+        let record = self.inner.get_record(reference)?;
+        let deleted = self.inner.delete_record(reference)?;
+        if let Some(record) = record {
+            self.invalidate(record.as_ref());
+        }
+        Ok(deleted)
+    }
+
+    fn scan(
+        &self,
+        order: ScanOrder,
+        after: Option<&Id>,
+        limit: usize,
+    ) -> Result<Vec<ScanEntry>, Error> {
+        self.inner.scan(order, after, limit)
+    }
+
+    fn backup_to(&self, path: &Path) -> Result<(), Error> {
+        self.inner.backup_to(path)
+    }
+
+    fn check_indexes(&self) -> Result<Option<Vec<FsckProblem>>, Error> {
+        self.inner.check_indexes()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::test_support::{InMemoryStore, build_record};
+
+    #[test]
+    fn repeated_reads_hit_the_cache() {
+        let store = CachedStore::new(InMemoryStore::default(), CacheConfig::default());
+        let record = build_record(b"popular profile");
+        store.put_record(record.as_ref()).unwrap();
+        let reference = record.id().to_reference();
+
+        for _ in 0..3 {
+            let fetched = store.get_record(&reference).unwrap().unwrap();
+            assert_eq!(fetched.as_bytes(), record.as_bytes());
+        }
+
+        let stats = store.cache_stats();
+        assert_eq!(stats.misses, 1);
+        assert_eq!(stats.hits, 2);
+        assert_eq!(stats.cached_bytes, record.as_bytes().len());
+    }
+
+    #[test]
+    fn misses_are_remembered_until_the_record_is_put() {
+        let store = CachedStore::new(InMemoryStore::default(), CacheConfig::default());
+        let record = build_record(b"late arrival");
+        let reference = record.id().to_reference();
+
+        assert!(store.get_record(&reference).unwrap().is_none());
+        assert!(store.get_record(&reference).unwrap().is_none());
+        assert_eq!(store.cache_stats().negative_hits, 1);
+
+        store.put_record(record.as_ref()).unwrap();
+        assert!(store.get_record(&reference).unwrap().is_some());
+
+        assert!(store.delete_record(&reference).unwrap());
+        assert!(store.get_record(&reference).unwrap().is_none());
+        assert!(!store.has_record(&reference).unwrap());
+    }
+
+    #[test]
+    fn least_recently_used_records_are_evicted_by_size() {
+        let records: Vec<_> = (0..3)
+            .map(|i| build_record(format!("record {i}").as_bytes()))
+            .collect();
+        let len = records[0].as_bytes().len();
+        let config = CacheConfig {
+            max_bytes: 2 * len,
+            ..Default::default()
+        };
+        let store = CachedStore::new(InMemoryStore::default(), config);
+        for record in &records {
+            store.put_record(record.as_ref()).unwrap();
+        }
+
+        let refs: Vec<_> = records.iter().map(|r| r.id().to_reference()).collect();
+        store.get_record(&refs[0]).unwrap();
+        store.get_record(&refs[1]).unwrap();
+        store.get_record(&refs[0]).unwrap(); // refresh 0, leaving 1 oldest
+        store.get_record(&refs[2]).unwrap(); // evicts 1
+
+        let before = store.cache_stats();
+        store.get_record(&refs[0]).unwrap();
+        store.get_record(&refs[1]).unwrap();
+        let after = store.cache_stats();
+        assert_eq!(after.hits - before.hits, 1);
+        assert_eq!(after.misses - before.misses, 1);
+        assert!(after.cached_bytes <= 2 * len);
+    }
+}
@@ -0,0 +1,313 @@
+use std::fmt;
+use std::net::{IpAddr, SocketAddr};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::{Arc, PoisonError, RwLock};
+
+use mosaic_net::{Approval, Approver};
+
+use crate::{Error, InnerError};
+
+/// A block of IP addresses, such as `192.0.2.0/24` or `2001:db8::/32`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Cidr {
+    network: IpAddr,
+    prefix_len: u8,
+}
+
+impl Cidr {
+    /// The block of addresses sharing the first `prefix_len` bits of `addr`.
+    /// IPv4-mapped IPv6 addresses give IPv4 blocks, with `prefix_len` counted
+    /// over the whole IPv6 address.
+    pub fn new(addr: IpAddr, prefix_len: u8) -> Result<Cidr, Error> {
+        let canonical = addr.to_canonical();
+        let prefix_len = if addr.is_ipv6() && canonical.is_ipv4() && prefix_len >= 96 {
+            prefix_len - 96
+        } else {
+            prefix_len
+        };
+        let addr = canonical;
+        let max = if addr.is_ipv4() { 32 } else { 128 };
+        if prefix_len > max {
+            return Err(InnerError::General(format!(
+                "prefix length /{prefix_len} is too long for {addr}"
+            ))
+            .into_err());
+        }
+        let network = match addr {
+            IpAddr::V4(v4) => {
+                let mask = u32::MAX
+                    .checked_shl(32 - u32::from(prefix_len))
+                    .unwrap_or(0);
+                IpAddr::V4((u32::from(v4) & mask).into())
+            }
+            IpAddr::V6(v6) => {
+                let mask = u128::MAX
+                    .checked_shl(128 - u32::from(prefix_len))
+                    .unwrap_or(0);
+                IpAddr::V6((u128::from(v6) & mask).into())
+            }
+        };
+        Ok(Cidr {
+            network,
+            prefix_len,
+        })
+    }
+
+    /// Whether `ip` is in this block. IPv4-mapped IPv6 addresses match IPv4
+    /// blocks.
+    #[must_use]
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        let ip = ip.to_canonical();
+        if ip.is_ipv4() != self.network.is_ipv4() {
+            return false;
+        }
+        Cidr::new(ip, self.prefix_len).is_ok_and(|block| block.network == self.network)
+    }
+}
+
+impl FromStr for Cidr {
+    type Err = Error;
+
+    /// Parse `addr/len`, or a bare address as a block of one
+    fn from_str(s: &str) -> Result<Cidr, Error> {
+        let invalid = || InnerError::General(format!("invalid CIDR block {s:?}")).into_err();
+        let (addr, prefix_len) = match s.split_once('/') {
+            Some((addr, len)) => (addr, Some(len.parse::<u8>().map_err(|_| invalid())?)),
+            None => (s, None),
+        };
+        let addr = addr.parse::<IpAddr>().map_err(|_| invalid())?;
+        match prefix_len {
+            Some(prefix_len) => Cidr::new(addr, prefix_len),
+            None => {
+                let addr = addr.to_canonical();
+                Cidr::new(addr, if addr.is_ipv4() { 32 } else { 128 })
+            }
+        }
+    }
+}
+
+impl fmt::Display for Cidr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.network, self.prefix_len)
+    }
+}
+
+/// Allow and deny lists of CIDR blocks.
+///
+/// An address in any deny block is refused. Otherwise it is approved if the
+/// allow list is empty or the address is in one of its blocks.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CidrRules {
+    /// Blocks approved, if non-empty; everything else is refused
+    pub allow: Vec<Cidr>,
+
+    /// Blocks always refused
+    pub deny: Vec<Cidr>,
+}
+
+impl CidrRules {
+    /// Parse rules, one per line: `allow <cidr>` or `deny <cidr>`. Blank lines
+    /// and everything after a `#` are ignored.
+    pub fn parse(text: &str) -> Result<CidrRules, Error> {
+        let mut rules = CidrRules::default();
+        for (number, line) in text.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or_default().trim();
+            if line.is_empty() {
+                continue;
+            }
+            let bad_line =
+                |why: String| InnerError::General(format!("line {}: {why}", number + 1)).into_err();
+            let mut words = line.split_whitespace();
+            let (Some(action), Some(block), None) = (words.next(), words.next(), words.next())
+            else {
+                return Err(bad_line(format!(
+                    "expected `allow <cidr>` or `deny <cidr>`, got {line:?}"
+                )));
+            };
+            let block: Cidr = block
+                .parse()
+                .map_err(|e: Error| bad_line(e.inner.to_string()))?;
+            match action {
+                "allow" => rules.allow.push(block),
+                "deny" => rules.deny.push(block),
+                _ => return Err(bad_line(format!("unknown action {action:?}"))),
+            }
+        }
+        Ok(rules)
+    }
+
+    /// Read and parse a rules file
+    pub fn load(path: &Path) -> Result<CidrRules, Error> {
+        let text = std::fs::read_to_string(path)?;
+        CidrRules::parse(&text)
+            .map_err(|e| InnerError::General(format!("{}: {}", path.display(), e.inner)).into_err())
+    }
+
+    /// Whether a client connecting from `ip` is approved
+    #[must_use]
+    pub fn is_allowed(&self, ip: IpAddr) -> bool {
+        if self.deny.iter().any(|block| block.contains(ip)) {
+            return false;
+        }
+        self.allow.is_empty() || self.allow.iter().any(|block| block.contains(ip))
+    }
+}
+
+/// An `Approver` that approves or refuses clients by their IP address using
+/// `CidrRules`, optionally loaded from a file.
+///
+/// The rules can be replaced or reloaded while the server runs; connections
+/// already accepted are not affected.
+#[derive(Debug)]
+pub struct CidrApprover {
+    path: Option<PathBuf>,
+    rules: RwLock<Arc<CidrRules>>,
+}
+
+impl CidrApprover {
+    /// Approve clients by fixed `rules`
+    #[must_use]
+    pub fn new(rules: CidrRules) -> CidrApprover {
+        CidrApprover {
+            path: None,
+            rules: RwLock::new(Arc::new(rules)),
+        }
+    }
+
+    /// Approve clients by the rules in the file at `path`, which `reload`
+    /// reads again
+    pub fn from_file(path: impl Into<PathBuf>) -> Result<CidrApprover, Error> {
+        let path = path.into();
+        let rules = CidrRules::load(&path)?;
+        Ok(CidrApprover {
+            path: Some(path),
+            rules: RwLock::new(Arc::new(rules)),
+        })
+    }
+
+    /// Read the rules file again. If it cannot be read or parsed, the current
+    /// rules stay in force and the error is returned.
+    pub fn reload(&self) -> Result<(), Error> {
+        let Some(path) = &self.path else {
+            return Err(
+                InnerError::General("this approver was not loaded from a file".to_owned())
+                    .into_err(),
+            );
+        };
+        self.set_rules(CidrRules::load(path)?);
+        Ok(())
+    }
+
+    /// Replace the rules
+    pub fn set_rules(&self, rules: CidrRules) {
+        *self.rules.write().unwrap_or_else(PoisonError::into_inner) = Arc::new(rules);
+    }
+
+    /// The rules currently in force
+    #[must_use]
+    pub fn rules(&self) -> Arc<CidrRules> {
+        self.rules
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clone()
+    }
+}
+
+impl Approver for CidrApprover {
+    fn is_client_allowed(&self, socket_addr: SocketAddr) -> Approval {
+        if self.rules().is_allowed(socket_addr.ip()) {
+            Approval::Approve
+        } else {
+            Approval::Deny
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ip(s: &str) -> IpAddr {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn blocks_match_their_prefix() {
+        let v4: Cidr = "192.0.2.77/24".parse().unwrap();
+        assert_eq!(v4.to_string(), "192.0.2.0/24");
+        assert!(v4.contains(ip("192.0.2.1")));
+        assert!(v4.contains(ip("::ffff:192.0.2.200")));
+        assert!(!v4.contains(ip("192.0.3.1")));
+        assert!(!v4.contains(ip("2001:db8::1")));
+
+        let v6: Cidr = "2001:db8::/32".parse().unwrap();
+        assert!(v6.contains(ip("2001:db8:ffff::1")));
+        assert!(!v6.contains(ip("2001:db9::1")));
+
+        let host: Cidr = "198.51.100.7".parse().unwrap();
+        assert!(host.contains(ip("198.51.100.7")));
+        assert!(!host.contains(ip("198.51.100.8")));
+
+        assert!(
+            "0.0.0.0/0"
+                .parse::<Cidr>()
+                .unwrap()
+                .contains(ip("203.0.113.1"))
+        );
+        assert!("192.0.2.0/33".parse::<Cidr>().is_err());
+        assert!("not an address".parse::<Cidr>().is_err());
+    }
+
+    #[test]
+    fn mapped_addresses_give_ipv4_blocks() {
+        let host: Cidr = "::ffff:192.0.2.1".parse().unwrap();
+        assert_eq!(host.to_string(), "192.0.2.1/32");
+        assert!(host.contains(ip("192.0.2.1")));
+        assert!(!host.contains(ip("192.0.2.2")));
+
+        let block: Cidr = "::ffff:192.0.2.77/120".parse().unwrap();
+        assert_eq!(block.to_string(), "192.0.2.0/24");
+    }
+
+    #[test]
+    fn deny_wins_over_allow() {
+        let rules = CidrRules::parse(
+            "# office and VPN\n\
+             allow 192.0.2.0/24\n\
+             allow 2001:db8::/32   # VPN\n\
+             \n\
+             deny 192.0.2.13\n",
+        )
+        .unwrap();
+        assert!(rules.is_allowed(ip("192.0.2.1")));
+        assert!(!rules.is_allowed(ip("192.0.2.13")));
+        assert!(rules.is_allowed(ip("2001:db8::5")));
+        assert!(!rules.is_allowed(ip("198.51.100.1")));
+
+        let deny_only = CidrRules::parse("deny 10.0.0.0/8").unwrap();
+        assert!(!deny_only.is_allowed(ip("10.1.2.3")));
+        assert!(deny_only.is_allowed(ip("198.51.100.1")));
+
+        assert!(CidrRules::parse("permit 10.0.0.0/8").is_err());
+        assert!(CidrRules::parse("allow").is_err());
+    }
+
+    #[test]
+    fn reload_keeps_old_rules_on_error() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("approver.rules");
+        std::fs::write(&path, "deny 192.0.2.0/24\n").unwrap();
+        let approver = CidrApprover::from_file(&path).unwrap();
+        assert!(!approver.rules().is_allowed(ip("192.0.2.1")));
+
+        std::fs::write(&path, "allow 192.0.2.0/24\n").unwrap();
+        approver.reload().unwrap();
+        assert!(approver.rules().is_allowed(ip("192.0.2.1")));
+
+        std::fs::write(&path, "allow 192.0.2.0/99\n").unwrap();
+        assert!(approver.reload().is_err());
+        assert!(approver.rules().is_allowed(ip("192.0.2.1")));
+        assert!(!approver.rules().is_allowed(ip("198.51.100.1")));
+    }
+}
@@ -2,10 +2,13 @@ use std::net::SocketAddr;
 
 use mosaic_core::{PublicKey, ResultCode};
 
+use crate::Role;
+
 pub struct ClientData {
     pub remote_address: SocketAddr,
     pub peer: Option<PublicKey>,
     pub mosaic_version: Option<u16>,
     pub applications: Option<Vec<u32>>,
     pub closing_result: Option<ResultCode>,
+    pub role: Role,
 }
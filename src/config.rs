@@ -1,16 +1,36 @@
 use std::fmt;
 use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
 
 use mosaic_core::{PublicKey, SecretKey};
 use mosaic_net::Approver;
 use std::sync::Arc;
 
-use crate::{Error, Store};
+use crate::{
+    BackupPolicy, ConnectionLimits, DuplicateFilterConfig, Error, ModerationPolicy, PeerAuthorizer,
+    ProofOfWork, QuotaPolicy, RateLimitConfig, ReadAccessPolicy, ReadMembers, RetentionPolicy,
+    RetentionReport, Store, Timeouts,
+};
 
 /// A trait for logging errors
 pub trait Logger: Send + Sync {
     /// Log a client error
     fn log_client_error(&self, e: Error, socket_addr: SocketAddr, pubkey: Option<PublicKey>);
+
+    /// Log an error from server background work that is not tied to a client
+    fn log_server_error(&self, e: Error) {
+        let _ = e;
+    }
+
+    /// Log the records removed by a retention pass
+    fn log_retention(&self, report: &RetentionReport) {
+        let _ = report;
+    }
+
+    /// Log a completed periodic backup
+    fn log_backup(&self, path: &Path) {
+        let _ = path;
+    }
 }
 
 /// A configuration for creating a Mosaic `Server`
@@ -30,6 +50,50 @@ pub struct ServerConfig<A: Approver, L: Logger> {
 
     /// Storage backend used for submissions
     pub store: Arc<dyn Store>,
+
+    /// Retention rules enforced by a background task, if any
+    pub retention: Option<RetentionPolicy>,
+
+    /// Per-author storage quotas checked on submission, if any
+    pub quotas: Option<QuotaPolicy>,
+
+    /// In-memory filter of stored ids used to answer re-published records as
+    /// duplicates without verifying them. Built from the store at startup.
+    pub duplicate_filter: Option<DuplicateFilterConfig>,
+
+    /// Periodic backups of the store taken by a background task, if any
+    pub backups: Option<BackupPolicy>,
+
+    /// Per-peer limits on how fast submissions and queries are accepted, if any
+    pub rate_limits: Option<RateLimitConfig>,
+
+    /// Caps on concurrent connections, checked as each connection arrives
+    pub connection_limits: Option<ConnectionLimits>,
+
+    /// How long a connection may take to say HELLO, sit idle, or send a message
+    pub timeouts: Timeouts,
+
+    /// Decides whether to serve each client once its key is known, and with
+    /// which role. Without one every client is served read-write.
+    pub peer_authorizer: Option<Arc<dyn PeerAuthorizer>>,
+
+    /// Work required of submitted records, if any. The difficulty can be
+    /// changed later through `Server::proof_of_work`.
+    pub proof_of_work: Option<ProofOfWork>,
+
+    /// File of blocked authors and records, created if missing. Usually kept
+    /// next to the store's data.
+    pub blocklist: Option<PathBuf>,
+
+    /// Review queue for submissions from untrusted authors, if any
+    pub moderation: Option<ModerationPolicy>,
+
+    /// Which records are private to their author and recipients, if any
+    pub read_access: Option<ReadAccessPolicy>,
+
+    /// Private server mode: only these members may read, if set. The members
+    /// can be changed later through `Server::read_members`.
+    pub read_members: Option<ReadMembers>,
     //pub listen_over_quic: bool,
     //pub listen_over_tcp: bool,
     //pub listen_over_websockets: bool,
@@ -43,6 +107,22 @@ impl<A: Approver, L: Logger> fmt::Debug for ServerConfig<A, L> {
             .field("approver", &"<approver>")
             .field("logger", &"<logger>")
             .field("store", &"<store>")
+            .field("retention", &self.retention)
+            .field("quotas", &self.quotas)
+            .field("duplicate_filter", &self.duplicate_filter)
+            .field("backups", &self.backups)
+            .field("rate_limits", &self.rate_limits)
+            .field("connection_limits", &self.connection_limits)
+            .field("timeouts", &self.timeouts)
+            .field(
+                "peer_authorizer",
+                &self.peer_authorizer.as_ref().map(|_| "<peer authorizer>"),
+            )
+            .field("proof_of_work", &self.proof_of_work)
+            .field("blocklist", &self.blocklist)
+            .field("moderation", &self.moderation)
+            .field("read_access", &self.read_access)
+            .field("read_members", &self.read_members)
             .finish()
     }
 }
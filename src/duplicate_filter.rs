@@ -0,0 +1,154 @@
+use std::sync::atomic::{AtomicU8, Ordering};
+
+use mosaic_core::Id;
+
+use crate::{Error, ScanOrder, Store};
+
+/// Records read from the store per scan while populating
+const POPULATE_BATCH: usize = 1024;
+
+/// Sizing of a `DuplicateFilter`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DuplicateFilterConfig {
+    /// Number of record ids the filter is sized for
+    pub expected_records: usize,
+
+    /// Desired false positive rate at `expected_records` (between 0 and 1)
+    pub false_positive_rate: f64,
+}
+
+impl Default for DuplicateFilterConfig {
+    fn default() -> DuplicateFilterConfig {
+        DuplicateFilterConfig {
+            expected_records: 1_000_000,
+            false_positive_rate: 0.01,
+        }
+    }
+}
+
+/// A counting Bloom filter of record ids held in the store
+///
+/// "Not present" answers are exact, so a submission whose id is not in the
+/// filter is certainly new. "Maybe present" answers must be confirmed with
+/// `Store::has_record`. Counting (rather than single bit) cells allow ids to
+/// be removed when records are deleted.
+pub struct DuplicateFilter {
+    cells: Vec<AtomicU8>,
+    hashes: u32,
+}
+
+impl DuplicateFilter {
+    /// Create an empty filter sized by `config`
+    #[must_use]
+    pub fn new(config: DuplicateFilterConfig) -> DuplicateFilter {
+        let n = config.expected_records.max(1) as f64;
+        let p = config.false_positive_rate.clamp(1e-9, 0.5);
+        let ln2 = std::f64::consts::LN_2;
+        let cells = (-(n * p.ln()) / (ln2 * ln2)).ceil().max(64.0) as usize;
+        let hashes = ((cells as f64 / n) * ln2).round().clamp(1.0, 16.0) as u32;
+        DuplicateFilter {
+            cells: (0..cells).map(|_| AtomicU8::new(0)).collect(),
+            hashes,
+        }
+    }
+
+    /// Create a filter sized by `config` holding every id in `store`
+    pub fn from_store(
+        store: &dyn Store,
+        config: DuplicateFilterConfig,
+    ) -> Result<DuplicateFilter, Error> {
+        let filter = DuplicateFilter::new(config);
+        let mut after = None;
+        loop {
+            let batch = store.scan(ScanOrder::Insertion, after.as_ref(), POPULATE_BATCH)?;
+            let Some(last) = batch.last() else {
+                break;
+            };
+            after = Some(last.id);
+            for entry in &batch {
+                filter.insert(&entry.id);
+            }
+        }
+        Ok(filter)
+    }
+
+    /// Record that `id` is stored
+    pub fn insert(&self, id: &Id) {
+        for cell in self.cells_for(id) {
+            // Saturated cells stay saturated; they can no longer be counted down.
+            let _ = self.cells[cell]
+                .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |c| c.checked_add(1));
+        }
+    }
+
+    /// Record that `id` was deleted. Only call this for ids that were inserted.
+    pub fn remove(&self, id: &Id) {
+        for cell in self.cells_for(id) {
+            let _ =
+                self.cells[cell].fetch_update(Ordering::Relaxed, Ordering::Relaxed, |c| match c {
+                    0 | u8::MAX => None,
+                    c => Some(c - 1),
+                });
+        }
+    }
+
+    /// False if `id` is certainly not stored; true if it may be
+    #[must_use]
+    pub fn might_contain(&self, id: &Id) -> bool {
+        self.cells_for(id)
+            .all(|cell| self.cells[cell].load(Ordering::Relaxed) > 0)
+    }
+
+    fn cells_for(&self, id: &Id) -> impl Iterator<Item = usize> + '_ {
+        // Ids are already cryptographic hashes (after the leading timestamp),
+        // so two slices of them serve as the hash pair for double hashing.
+        let bytes = id.as_bytes();
+        let h1 = u64::from_le_bytes(bytes[8..16].try_into().unwrap_or_default());
+        let h2 = u64::from_le_bytes(bytes[16..24].try_into().unwrap_or_default()) | 1;
+        let len = self.cells.len() as u64;
+        (0..u64::from(self.hashes))
+            .map(move |i| (h1.wrapping_add(i.wrapping_mul(h2)) % len) as usize)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::test_support::{InMemoryStore, build_record};
+
+    #[test]
+    fn inserted_ids_are_found_and_removed_ids_forgotten() {
+        let filter = DuplicateFilter::new(DuplicateFilterConfig {
+            expected_records: 100,
+            false_positive_rate: 0.001,
+        });
+        let kept = build_record(b"kept").id();
+        let removed = build_record(b"removed").id();
+        let never = build_record(b"never").id();
+
+        filter.insert(&kept);
+        filter.insert(&removed);
+        assert!(filter.might_contain(&kept));
+        assert!(filter.might_contain(&removed));
+        assert!(!filter.might_contain(&never));
+
+        filter.remove(&removed);
+        assert!(filter.might_contain(&kept));
+        assert!(!filter.might_contain(&removed));
+    }
+
+    #[test]
+    fn populates_from_store() {
+        let store = InMemoryStore::default();
+        let records: Vec<_> = (0..50)
+            .map(|i| build_record(format!("record {i}").as_bytes()))
+            .collect();
+        for record in &records {
+            store.put_record(record.as_ref()).unwrap();
+        }
+
+        let filter = DuplicateFilter::from_store(&store, DuplicateFilterConfig::default()).unwrap();
+        assert!(records.iter().all(|r| filter.might_contain(&r.id())));
+    }
+}
@@ -2,6 +2,8 @@ use std::convert::Infallible;
 use std::error::Error as StdError;
 use std::panic::Location;
 
+use crate::{StoreError, TimeoutKind};
+
 /// A Mosaic server error
 #[derive(Debug)]
 pub struct Error {
@@ -28,12 +30,21 @@ pub enum InnerError {
     /// General error
     General(String),
 
+    /// I/O
+    Io(std::io::Error),
+
     /// Mosaic Core
     MosaicCore(mosaic_core::Error),
 
     /// Mosaic Net
     MosaicNet(mosaic_net::Error),
 
+    /// Storage backend
+    Store(StoreError),
+
+    /// A connection timed out
+    Timeout(TimeoutKind),
+
     /// Tokio Join
     TokioJoin(tokio::task::JoinError),
 }
@@ -42,8 +53,11 @@ impl std::fmt::Display for InnerError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
             InnerError::General(s) => write!(f, "General Error: {s}"),
+            InnerError::Io(e) => write!(f, "I/O: {e}"),
             InnerError::MosaicCore(e) => write!(f, "Mosaic Core: {e}"),
             InnerError::MosaicNet(e) => write!(f, "Mosaic Net: {e}"),
+            InnerError::Store(e) => write!(f, "Store: {e}"),
+            InnerError::Timeout(kind) => write!(f, "Timeout: {kind}"),
             InnerError::TokioJoin(e) => write!(f, "Tokio Join: {e}"),
         }
     }
@@ -52,8 +66,10 @@ impl std::fmt::Display for InnerError {
 impl StdError for InnerError {
     fn source(&self) -> Option<&(dyn StdError + 'static)> {
         match self {
+            InnerError::Io(e) => Some(e),
             InnerError::MosaicCore(e) => Some(e),
             InnerError::MosaicNet(e) => Some(e),
+            InnerError::Store(e) => Some(e),
             _ => None,
         }
     }
@@ -126,6 +142,16 @@ impl From<String> for Error {
     }
 }
 
+impl From<std::io::Error> for Error {
+    #[track_caller]
+    fn from(e: std::io::Error) -> Self {
+        Error {
+            inner: InnerError::Io(e),
+            location: Location::caller(),
+        }
+    }
+}
+
 impl From<mosaic_core::Error> for Error {
     #[track_caller]
     fn from(e: mosaic_core::Error) -> Self {
@@ -146,6 +172,16 @@ impl From<mosaic_net::Error> for Error {
     }
 }
 
+impl From<StoreError> for Error {
+    #[track_caller]
+    fn from(e: StoreError) -> Self {
+        Error {
+            inner: InnerError::Store(e),
+            location: Location::caller(),
+        }
+    }
+}
+
 impl From<tokio::task::JoinError> for Error {
     #[track_caller]
     fn from(e: tokio::task::JoinError) -> Self {
@@ -0,0 +1,312 @@
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Mutex, PoisonError};
+use std::time::Duration;
+
+use mosaic_core::{Id, OwnedRecord, PublicKey, Record, Reference};
+
+use crate::{
+    Error, FsckProblem, PutResult, RecordTotals, ScanEntry, ScanOrder, Store, StoreError,
+    StoreErrorKind, StoreStats,
+};
+
+/// A `Store` method that `FaultyStore` can inject faults into
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum StoreMethod {
+    PutRecord,
+    HasRecord,
+    GetRecord,
+    Stats,
+    AuthorStats,
+    DeleteRecord,
+    Scan,
+    Backup,
+}
+
+impl StoreMethod {
+    /// Every method of the `Store` trait
+    pub const ALL: [StoreMethod; 8] = [
+        StoreMethod::PutRecord,
+        StoreMethod::HasRecord,
+        StoreMethod::GetRecord,
+        StoreMethod::Stats,
+        StoreMethod::AuthorStats,
+        StoreMethod::DeleteRecord,
+        StoreMethod::Scan,
+        StoreMethod::Backup,
+    ];
+
+    /// The methods that modify the store
+    pub const WRITES: [StoreMethod; 2] = [StoreMethod::PutRecord, StoreMethod::DeleteRecord];
+}
+
+/// Faults injected into the calls of one `Store` method
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Fault {
+    /// Fraction (0.0 to 1.0) of calls that fail
+    pub failure_rate: f64,
+
+    /// Kind of the `StoreError` returned by failed calls
+    pub kind: StoreErrorKind,
+
+    /// Delay added to every call, failed or not
+    pub latency: Duration,
+}
+
+impl Default for Fault {
+    fn default() -> Fault {
+        Fault {
+            failure_rate: 0.0,
+            kind: StoreErrorKind::Transient,
+            latency: Duration::ZERO,
+        }
+    }
+}
+
+impl Fault {
+    /// Fail every call with an error of `kind`
+    #[must_use]
+    pub fn always(kind: StoreErrorKind) -> Fault {
+        Fault::sometimes(1.0, kind)
+    }
+
+    /// Fail about `failure_rate` of calls with an error of `kind`
+    #[must_use]
+    pub fn sometimes(failure_rate: f64, kind: StoreErrorKind) -> Fault {
+        Fault {
+            failure_rate,
+            kind,
+            ..Default::default()
+        }
+    }
+
+    /// Delay every call by `latency` without failing it
+    #[must_use]
+    pub fn slow(latency: Duration) -> Fault {
+        Fault {
+            latency,
+            ..Default::default()
+        }
+    }
+}
+
+/// A `Store` decorator that injects failures, latency and outages into calls
+/// to the wrapped store, for testing how the server copes with storage trouble.
+///
+/// Which calls fail is decided by a pseudo-random generator started from the
+/// given seed, so a test making the same calls sees the same failures. Latency
+/// is a blocking sleep, as the `Store` methods are synchronous.
+pub struct FaultyStore<S: Store> {
+    inner: S,
+    state: Mutex<FaultState>,
+    injected: AtomicU64,
+}
+
+struct FaultState {
+    faults: HashMap<StoreMethod, Fault>,
+    // Methods failing every call until the outage ends
+    outages: HashMap<StoreMethod, StoreErrorKind>,
+    rng: u64,
+}
+
+impl FaultState {
+    /// Next value of a splitmix64 sequence, as a fraction in [0, 1)
+    fn next_fraction(&mut self) -> f64 {
+        self.rng = self.rng.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.rng;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^= z >> 31;
+        (z >> 11) as f64 / (1_u64 << 53) as f64
+    }
+}
+
+impl<S: Store> FaultyStore<S> {
+    /// Wrap `inner`, initially passing every call through unchanged
+    pub fn new(inner: S, seed: u64) -> FaultyStore<S> {
+        FaultyStore {
+            inner,
+            state: Mutex::new(FaultState {
+                faults: HashMap::new(),
+                outages: HashMap::new(),
+                rng: seed,
+            }),
+            injected: AtomicU64::new(0),
+        }
+    }
+
+    /// Set the faults injected into calls of `method`
+    #[must_use]
+    pub fn with_fault(self, method: StoreMethod, fault: Fault) -> FaultyStore<S> {
+        self.set_fault(method, fault);
+        self
+    }
+
+    /// The wrapped store
+    pub fn inner(&self) -> &S {
+        &self.inner
+    }
+
+    /// Replace the faults injected into calls of `method`
+    pub fn set_fault(&self, method: StoreMethod, fault: Fault) {
+        self.lock().faults.insert(method, fault);
+    }
+
+    /// Stop injecting random failures and latency into every method
+    pub fn clear_faults(&self) {
+        self.lock().faults.clear();
+    }
+
+    /// Make every call of `methods` fail with `kind` until `end_outage`
+    pub fn begin_outage(&self, methods: &[StoreMethod], kind: StoreErrorKind) {
+        let mut state = self.lock();
+        for method in methods {
+            state.outages.insert(*method, kind);
+        }
+    }
+
+    /// End all outages started with `begin_outage`
+    pub fn end_outage(&self) {
+        self.lock().outages.clear();
+    }
+
+    /// Number of calls failed on purpose so far
+    pub fn injected_failures(&self) -> u64 {
+        self.injected.load(Ordering::Relaxed)
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, FaultState> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Apply the configured latency to a call of `method`, then decide whether
+    /// it fails
+    fn inject(&self, method: StoreMethod) -> Result<(), Error> {
+        let (latency, failure) = {
+            let mut state = self.lock();
+            let fault = state.faults.get(&method).copied().unwrap_or_default();
+            let failure = match state.outages.get(&method) {
+                Some(kind) => Some(*kind),
+                None if fault.failure_rate > 0.0 && state.next_fraction() < fault.failure_rate => {
+                    Some(fault.kind)
+                }
+                None => None,
+            };
+            (fault.latency, failure)
+        };
+
+        if !latency.is_zero() {
+            std::thread::sleep(latency);
+        }
+        match failure {
+            Some(kind) => {
+                self.injected.fetch_add(1, Ordering::Relaxed);
+                Err(StoreError::new(kind, format!("injected {method:?} failure")).into())
+            }
+            None => Ok(()),
+        }
+    }
+}
+
+impl<S: Store> Store for FaultyStore<S> {
+    fn put_record(&self, record: &Record) -> Result<PutResult, Error> {
+        self.inject(StoreMethod::PutRecord)?;
+        self.inner.put_record(record)
+    }
+
+    fn has_record(&self, reference: &Reference) -> Result<bool, Error> {
+        self.inject(StoreMethod::HasRecord)?;
+        self.inner.has_record(reference)
+    }
+
+    fn get_record(&self, reference: &Reference) -> Result<Option<OwnedRecord>, Error> {
+        self.inject(StoreMethod::GetRecord)?;
+        self.inner.get_record(reference)
+    }
+
+    fn stats(&self) -> Result<StoreStats, Error> {
+        self.inject(StoreMethod::Stats)?;
+        self.inner.stats()
+    }
+
+    fn author_stats(&self, author: &PublicKey) -> Result<RecordTotals, Error> {
+        self.inject(StoreMethod::AuthorStats)?;
+        self.inner.author_stats(author)
+    }
+
+    fn delete_record(&self, reference: &Reference) -> Result<bool, Error> {
+        self.inject(StoreMethod::DeleteRecord)?;
+        self.inner.delete_record(reference)
+    }
+
+    fn scan(
+        &self,
+        order: ScanOrder,
+        after: Option<&Id>,
+        limit: usize,
+    ) -> Result<Vec<ScanEntry>, Error> {
+        self.inject(StoreMethod::Scan)?;
+        self.inner.scan(order, after, limit)
+    }
+
+    fn backup_to(&self, path: &Path) -> Result<(), Error> {
+        self.inject(StoreMethod::Backup)?;
+        self.inner.backup_to(path)
+    }
+
+    fn check_indexes(&self) -> Result<Option<Vec<FsckProblem>>, Error> {
+        self.inner.check_indexes()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::test_support::{InMemoryStore, build_record};
+
+    #[test]
+    fn failures_follow_the_seed() {
+        let outcomes = |seed| {
+            let store = FaultyStore::new(InMemoryStore::default(), seed).with_fault(
+                StoreMethod::PutRecord,
+                Fault::sometimes(0.5, StoreErrorKind::Io),
+            );
+            (0..32)
+                .map(|i| {
+                    let record = build_record(format!("record {i}").as_bytes());
+                    store.put_record(record.as_ref()).is_ok()
+                })
+                .collect::<Vec<bool>>()
+        };
+
+        let first = outcomes(7);
+        assert_eq!(first, outcomes(7));
+        assert!(first.iter().any(|ok| *ok));
+        assert!(first.iter().any(|ok| !*ok));
+    }
+
+    #[test]
+    fn outages_fail_only_the_chosen_methods() {
+        let store = FaultyStore::new(InMemoryStore::default(), 1);
+        let record = build_record(b"written before the outage");
+        store.put_record(record.as_ref()).unwrap();
+        let reference = record.id().to_reference();
+
+        store.begin_outage(&StoreMethod::WRITES, StoreErrorKind::OutOfSpace);
+        let err = store
+            .put_record(build_record(b"refused").as_ref())
+            .unwrap_err();
+        let crate::InnerError::Store(store_error) = &err.inner else {
+            panic!("expected a store error, got {err}");
+        };
+        assert_eq!(store_error.kind(), StoreErrorKind::OutOfSpace);
+        assert!(store.delete_record(&reference).is_err());
+        assert!(store.get_record(&reference).unwrap().is_some());
+
+        store.end_outage();
+        assert!(store.delete_record(&reference).unwrap());
+        assert_eq!(store.injected_failures(), 2);
+    }
+}
@@ -0,0 +1,358 @@
+use std::fmt;
+use std::path::Path;
+
+use mosaic_core::{Id, OwnedRecord};
+
+use crate::{Error, RecordTotals, ScanEntry, ScanOrder, Store, hex};
+
+/// Records read from the store per scan
+const CHECK_BATCH: usize = 256;
+
+/// A problem found by `check_store`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FsckProblem {
+    /// The stored bytes fail signature or hash verification
+    InvalidRecord { id: Id, reason: String },
+
+    /// The record verifies but is held under a different id than its own
+    IdMismatch { indexed: Id, actual: Id },
+
+    /// Looking the record up by id does not return it
+    IdIndexMismatch { id: Id },
+
+    /// Looking the record up by its address finds nothing, or an older version
+    AddressIndexMismatch { id: Id },
+
+    /// An index the backend keeps beside its records disagrees with them
+    IndexMismatch {
+        index: &'static str,
+        id: Id,
+        reason: String,
+    },
+
+    /// The store's statistics disagree with the records actually present
+    StatsMismatch {
+        reported: RecordTotals,
+        counted: RecordTotals,
+    },
+}
+
+impl FsckProblem {
+    /// True for problems that `check_store` repairs by quarantining the record
+    #[must_use]
+    pub fn is_bad_record(&self) -> bool {
+        matches!(
+            self,
+            FsckProblem::InvalidRecord { .. } | FsckProblem::IdMismatch { .. }
+        )
+    }
+}
+
+impl fmt::Display for FsckProblem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FsckProblem::InvalidRecord { id, reason } => {
+                write!(
+                    f,
+                    "record {} is invalid: {reason}",
+                    hex::encode(id.as_bytes())
+                )
+            }
+            FsckProblem::IdMismatch { indexed, actual } => write!(
+                f,
+                "record stored as {} has id {}",
+                hex::encode(indexed.as_bytes()),
+                hex::encode(actual.as_bytes())
+            ),
+            FsckProblem::IdIndexMismatch { id } => {
+                write!(
+                    f,
+                    "record {} is not returned by an id lookup",
+                    hex::encode(id.as_bytes())
+                )
+            }
+            FsckProblem::AddressIndexMismatch { id } => {
+                write!(
+                    f,
+                    "record {} is not reachable by its address",
+                    hex::encode(id.as_bytes())
+                )
+            }
+            FsckProblem::IndexMismatch { index, id, reason } => write!(
+                f,
+                "record {} disagrees with the {index} index: {reason}",
+                hex::encode(id.as_bytes())
+            ),
+            FsckProblem::StatsMismatch { reported, counted } => write!(
+                f,
+                "statistics report {} records / {} bytes but {} records / {} bytes are stored",
+                reported.records, reported.bytes, counted.records, counted.bytes
+            ),
+        }
+    }
+}
+
+/// Outcome of `check_store`
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FsckReport {
+    /// Number of records examined
+    pub records_checked: u64,
+
+    /// Everything found wrong, in the order it was found
+    pub problems: Vec<FsckProblem>,
+
+    /// Number of bad records moved to quarantine and deleted from the store
+    pub quarantined: u64,
+
+    /// Whether the store's own indexes were checked (see `Store::check_indexes`)
+    pub indexes_checked: bool,
+}
+
+impl FsckReport {
+    /// True if no problems were found
+    #[must_use]
+    pub fn is_clean(&self) -> bool {
+        self.problems.is_empty()
+    }
+}
+
+/// Check the integrity of every record in `store`.
+///
+/// Each record is re-verified (signature and id) and looked up again by id and
+/// by address, the totals are compared with `Store::stats`, and the indexes the
+/// backend keeps beside its records are checked with `Store::check_indexes`
+/// where it supports that. Intended to be run while the server is stopped.
+///
+/// If `quarantine_dir` is given, records that fail verification are written
+/// there (one file per record, named by the hex id under which they were held)
+/// and then deleted from the store.
+pub fn check_store(store: &dyn Store, quarantine_dir: Option<&Path>) -> Result<FsckReport, Error> {
+    if let Some(dir) = quarantine_dir {
+        std::fs::create_dir_all(dir)?;
+    }
+
+    let mut report = FsckReport::default();
+    let mut counted = RecordTotals::default();
+    let mut after = None;
+    loop {
+        let batch = store.scan(ScanOrder::Insertion, after.as_ref(), CHECK_BATCH)?;
+        let Some(last) = batch.last() else {
+            break;
+        };
+        after = Some(last.id);
+
+        for entry in &batch {
+            report.records_checked += 1;
+            counted.add(entry.bytes.len() as u64);
+
+            let problems = check_entry(store, entry)?;
+            let bad = problems.iter().any(FsckProblem::is_bad_record);
+            report.problems.extend(problems);
+
+            if let (true, Some(dir)) = (bad, quarantine_dir) {
+                std::fs::write(
+                    dir.join(format!("{}.record", hex::encode(entry.id.as_bytes()))),
+                    &entry.bytes,
+                )?;
+                if store.delete_record(&entry.id.to_reference())? {
+                    counted.remove(entry.bytes.len() as u64);
+                    report.quarantined += 1;
+                }
+            }
+        }
+    }
+
+    if let Some(problems) = store.check_indexes()? {
+        report.indexes_checked = true;
+        report.problems.extend(problems);
+    }
+
+    let reported = store.stats()?.total;
+    if reported != counted {
+        report
+            .problems
+            .push(FsckProblem::StatsMismatch { reported, counted });
+    }
+
+    Ok(report)
+}
+
+fn check_entry(store: &dyn Store, entry: &ScanEntry) -> Result<Vec<FsckProblem>, Error> {
+    let record = match OwnedRecord::from_vec(entry.bytes.clone()) {
+        Ok(record) => record,
+        Err(e) => {
+            return Ok(vec![FsckProblem::InvalidRecord {
+                id: entry.id,
+                reason: e.to_string(),
+            }]);
+        }
+    };
+
+    let mut problems = Vec::new();
+    if record.id() != entry.id {
+        problems.push(FsckProblem::IdMismatch {
+            indexed: entry.id,
+            actual: record.id(),
+        });
+        return Ok(problems);
+    }
+
+    let by_id = store.get_record(&entry.id.to_reference())?;
+    if by_id.is_none_or(|found| found.as_bytes() != record.as_bytes()) {
+        problems.push(FsckProblem::IdIndexMismatch { id: entry.id });
+    }
+
+    // An address lookup yields the newest version, which may be a later record.
+    let by_address = store.get_record(&record.address().to_reference())?;
+    let reachable = by_address.is_some_and(|found| {
+        found.address() == record.address() && found.timestamp() >= record.timestamp()
+    });
+    if !reachable {
+        problems.push(FsckProblem::AddressIndexMismatch { id: entry.id });
+    }
+
+    Ok(problems)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use mosaic_core::Reference;
+
+    use crate::test_support::{InMemoryStore, build_record};
+    use crate::{PutResult, StoreStats};
+
+    /// Serves one record's bytes damaged, as if the disk had corrupted it
+    struct CorruptingStore {
+        inner: InMemoryStore,
+        damaged: Id,
+    }
+
+    impl Store for CorruptingStore {
+        fn put_record(&self, record: &mosaic_core::Record) -> Result<PutResult, Error> {
+            self.inner.put_record(record)
+        }
+
+        fn has_record(&self, reference: &Reference) -> Result<bool, Error> {
+            self.inner.has_record(reference)
+        }
+
+        fn get_record(&self, reference: &Reference) -> Result<Option<OwnedRecord>, Error> {
+            self.inner.get_record(reference)
+        }
+
+        fn stats(&self) -> Result<StoreStats, Error> {
+            self.inner.stats()
+        }
+
+        fn delete_record(&self, reference: &Reference) -> Result<bool, Error> {
+            self.inner.delete_record(reference)
+        }
+
+        fn scan(
+            &self,
+            order: ScanOrder,
+            after: Option<&Id>,
+            limit: usize,
+        ) -> Result<Vec<ScanEntry>, Error> {
+            let mut entries = self.inner.scan(order, after, limit)?;
+            for entry in &mut entries {
+                if entry.id == self.damaged {
+                    let last = entry.bytes.len() - 1;
+                    entry.bytes[last] ^= 0xFF;
+                }
+            }
+            Ok(entries)
+        }
+    }
+
+    #[test]
+    fn clean_store_passes() {
+        let store = InMemoryStore::default();
+        for i in 0..3 {
+            let record = build_record(format!("fsck {i}").as_bytes());
+            store.put_record(record.as_ref()).unwrap();
+        }
+
+        let report = check_store(&store, None).unwrap();
+        assert_eq!(report.records_checked, 3);
+        assert!(report.is_clean(), "{:?}", report.problems);
+    }
+
+    #[test]
+    fn bad_record_is_reported_and_quarantined() {
+        let good = build_record(b"good");
+        let bad = build_record(b"bad");
+        let store = CorruptingStore {
+            inner: InMemoryStore::default(),
+            damaged: bad.id(),
+        };
+        store.put_record(good.as_ref()).unwrap();
+        store.put_record(bad.as_ref()).unwrap();
+
+        let report = check_store(&store, None).unwrap();
+        assert_eq!(report.problems.len(), 1);
+        assert!(matches!(
+            &report.problems[0],
+            FsckProblem::InvalidRecord { id, .. } if *id == bad.id()
+        ));
+        assert_eq!(report.quarantined, 0);
+        assert_eq!(store.inner.record_count(), 2);
+
+        let quarantine = tempfile::tempdir().unwrap();
+        let report = check_store(&store, Some(quarantine.path())).unwrap();
+        assert_eq!(report.quarantined, 1);
+        assert_eq!(store.inner.record_count(), 1);
+        assert!(store.inner.contains(good.id().as_bytes()));
+        assert_eq!(std::fs::read_dir(quarantine.path()).unwrap().count(), 1);
+    }
+
+    #[cfg(feature = "lmdb")]
+    #[test]
+    fn corrupt_lmdb_record_is_quarantined() {
+        let dir = tempfile::tempdir().unwrap();
+        let marker = b"this payload is damaged on disk";
+        let good = build_record(b"good");
+        let bad = build_record(marker);
+        {
+            let store = crate::LmdbStore::open(dir.path(), 1).unwrap();
+            store.put_record(good.as_ref()).unwrap();
+            store.put_record(bad.as_ref()).unwrap();
+        }
+
+        // Damage the stored payload behind the store's back.
+        let mut damaged = 0;
+        for entry in std::fs::read_dir(dir.path()).unwrap() {
+            let path = entry.unwrap().path();
+            let mut bytes = std::fs::read(&path).unwrap();
+            let found: Vec<usize> = bytes
+                .windows(marker.len())
+                .enumerate()
+                .filter(|(_, window)| window == marker)
+                .map(|(at, _)| at)
+                .collect();
+            for &at in &found {
+                bytes[at] ^= 0xFF;
+            }
+            if !found.is_empty() {
+                std::fs::write(&path, &bytes).unwrap();
+                damaged += found.len();
+            }
+        }
+        assert!(damaged > 0);
+
+        let store = crate::LmdbStore::open(dir.path(), 1).unwrap();
+        assert!(store.get_record(&bad.id().to_reference()).is_err());
+
+        let quarantine = tempfile::tempdir().unwrap();
+        let report = check_store(&store, Some(quarantine.path())).unwrap();
+        assert!(report.problems.iter().any(|problem| matches!(
+            problem,
+            FsckProblem::InvalidRecord { id, .. } if *id == bad.id()
+        )));
+        assert_eq!(report.quarantined, 1);
+        assert!(!store.has_record(&bad.id().to_reference()).unwrap());
+        assert!(store.has_record(&good.id().to_reference()).unwrap());
+    }
+}
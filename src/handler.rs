@@ -1,24 +1,136 @@
 use std::sync::Arc;
+use std::time::Instant;
 
-use mosaic_core::{Message, MessageType, OwnedRecord, QueryId, Record, ResultCode};
+use mosaic_core::{Id, Message, MessageType, OwnedRecord, PublicKey, QueryId, Record, ResultCode};
 
+use crate::quota::QuotaLocks;
+use crate::rate_limit::{RateLimiter, Traffic};
 use crate::{
-    Error, InnerError, Logger, PutResult, Store, SubmissionValidationError, client::ClientData,
-    validate_submission,
+    Blocklist, DuplicateFilter, Error, InnerError, Logger, ModerationPolicy, PeerAuthorizer,
+    ProofOfWork, PutResult, QuotaPolicy, RateLimitConfig, ReadAccessPolicy, ReadMembers,
+    RecordTotals, Role, Store, SubmissionValidationError, client::ClientData, validate_submission,
 };
 
 const SUPPORTED_MAJOR_VERSION: u8 = 0;
 
+/// Storage, logging and policies shared by the handlers of every connection
+pub(crate) struct HandlerContext<L: Logger> {
+    pub store: Arc<dyn Store>,
+    pub logger: Arc<L>,
+    pub quotas: Option<QuotaPolicy>,
+    pub quota_locks: QuotaLocks,
+    pub duplicates: Option<Arc<DuplicateFilter>>,
+    pub rate_limiter: Option<RateLimiter>,
+    pub authorizer: Option<Arc<dyn PeerAuthorizer>>,
+    pub pow: Option<Arc<ProofOfWork>>,
+    pub blocklist: Option<Arc<Blocklist>>,
+    pub moderation: Option<ModerationPolicy>,
+    pub read_access: Option<ReadAccessPolicy>,
+    pub read_members: Option<Arc<ReadMembers>>,
+}
+
+impl<L: Logger> HandlerContext<L> {
+    /// A context with no optional policies enabled
+    pub fn new(store: Arc<dyn Store>, logger: Arc<L>) -> HandlerContext<L> {
+        HandlerContext {
+            store,
+            logger,
+            quotas: None,
+            quota_locks: QuotaLocks::default(),
+            duplicates: None,
+            rate_limiter: None,
+            authorizer: None,
+            pow: None,
+            blocklist: None,
+            moderation: None,
+            read_access: None,
+            read_members: None,
+        }
+    }
+}
+
+/// Outcome of checking a message against the rate limits
+pub(crate) enum RateCheck {
+    /// Within limits (or not rate limited at all); dispatch it
+    Allowed,
+
+    /// Over the limit; send the refusal, if the message can be answered
+    Refused(Option<Message>),
+
+    /// Over the limit too many times; close the connection
+    Close,
+}
+
+/// Check `message` against its peer's rate limits, counting refusals on this
+/// connection in `violations`.
+pub(crate) fn check_rate_limit<L: Logger>(
+    message: &Message,
+    client_data: &ClientData,
+    violations: &mut u32,
+    ctx: &HandlerContext<L>,
+) -> RateCheck {
+    let Some(limiter) = &ctx.rate_limiter else {
+        return RateCheck::Allowed;
+    };
+    if client_data.role.is_admin() {
+        return RateCheck::Allowed;
+    }
+    let Some(traffic) = Traffic::of(message.message_type()) else {
+        return RateCheck::Allowed;
+    };
+    let ip = client_data.remote_address.ip();
+    if limiter.allow(traffic, ip, client_data.peer, Instant::now()) {
+        return RateCheck::Allowed;
+    }
+
+    *violations += 1;
+    if *violations > limiter.max_violations() {
+        return RateCheck::Close;
+    }
+
+    let code = RateLimitConfig::RESULT_CODE;
+    let refusal = match traffic {
+        Traffic::Write => {
+            extract_record_id(message).map(|id| Message::new_submission_result(id, code))
+        }
+        Traffic::Read => message
+            .query_id()
+            .map(|query_id| Message::new_query_closed(query_id, code)),
+    };
+    RateCheck::Refused(refusal)
+}
+
+/// In private server mode, the `QueryClosed` answering a GET, QUERY or
+/// SUBSCRIBE from an anonymous peer or one who is not a member
+pub(crate) fn refuse_non_member<L: Logger>(
+    message: &Message,
+    client_data: &ClientData,
+    ctx: &HandlerContext<L>,
+) -> Option<Message> {
+    let members = ctx.read_members.as_ref()?;
+    if Traffic::of(message.message_type()) != Some(Traffic::Read)
+        || client_data.role.is_admin()
+        || members.may_read(client_data.peer)
+    {
+        return None;
+    }
+    let query_id = message.query_id()?;
+    Some(Message::new_query_closed(
+        query_id,
+        ReadMembers::RESULT_CODE,
+    ))
+}
+
 pub(crate) struct GetResponse {
     pub query_id: QueryId,
     pub records: Vec<OwnedRecord>,
     pub result_code: ResultCode,
 }
 
-pub(crate) fn handle_get(
+pub(crate) fn handle_get<L: Logger>(
     message: &Message,
     client_data: &ClientData,
-    store: &Arc<dyn Store>,
+    ctx: &HandlerContext<L>,
 ) -> Result<GetResponse, Error> {
     let Some(query_id) = message.query_id() else {
         return Err(InnerError::General("GET message missing query id".to_owned()).into_err());
@@ -42,8 +154,25 @@ pub(crate) fn handle_get(
 
     let mut found_records = Vec::with_capacity(references.len());
     for reference in references {
-        if let Some(record) = store.get_record(&reference)? {
-            found_records.push(record);
+        match ctx.store.get_record(&reference) {
+            Ok(Some(record)) if is_blocked(ctx, record.as_ref()) => {}
+            // Records the peer may not read are reported as not found
+            Ok(Some(record)) if !may_read(ctx, record.as_ref(), client_data) => {}
+            Ok(Some(record)) => found_records.push(record),
+            Ok(None) => {}
+            Err(store_err) => {
+                let result_code = store_error_result_code(&store_err);
+                ctx.logger.log_client_error(
+                    store_err,
+                    client_data.remote_address,
+                    client_data.peer,
+                );
+                return Ok(GetResponse {
+                    query_id,
+                    records: found_records,
+                    result_code,
+                });
+            }
         }
     }
 
@@ -63,8 +192,7 @@ pub(crate) fn handle_get(
 pub(crate) async fn handle_mosaic_message<L: Logger>(
     message: Message,
     client_data: &mut ClientData,
-    store: &Arc<dyn Store>,
-    logger: &Arc<L>,
+    ctx: &HandlerContext<L>,
 ) -> Result<Option<Message>, Error> {
     match message.message_type() {
         MessageType::Hello => handle_hello(message, client_data),
@@ -79,7 +207,7 @@ pub(crate) async fn handle_mosaic_message<L: Logger>(
             todo!()
         }
         MessageType::Submission => {
-            let response = handle_submission(message, client_data, store, logger)?;
+            let response = handle_submission(message, client_data, ctx)?;
             Ok(Some(response))
         }
         MessageType::Unrecognized => Ok(Some(Message::new_unrecognized())),
@@ -142,13 +270,79 @@ fn handle_hello(message: Message, client_data: &mut ClientData) -> Result<Option
 fn handle_submission<L: Logger>(
     message: Message,
     client_data: &mut ClientData,
-    store: &Arc<dyn Store>,
-    logger: &Arc<L>,
+    ctx: &HandlerContext<L>,
 ) -> Result<Message, Error> {
-    match validate_submission(&message, client_data) {
+    let logger = &ctx.logger;
+
+    if !client_data.role.can_write() {
+        return Ok(match extract_record_id(&message) {
+            Some(id) => Message::new_submission_result(id, Role::RESULT_CODE),
+            None => Message::new_closing(ResultCode::Invalid),
+        });
+    }
+
+    if let Some(id) = blocked_submission(ctx, &message) {
+        return Ok(Message::new_submission_result(id, Blocklist::RESULT_CODE));
+    }
+
+    // A store error here falls through to the normal path, which reports it
+    let known_duplicate = match &ctx.duplicates {
+        Some(filter) => find_known_duplicate(filter, &message, client_data, &ctx.store)
+            .ok()
+            .flatten(),
+        None => None,
+    };
+    if let Some(id) = known_duplicate {
+        return Ok(Message::new_submission_result(id, ResultCode::Duplicate));
+    }
+
+    match validate_submission(&message, client_data, ctx.pow.as_deref()) {
         Ok(record) => {
             let id = record.id();
-            match store.put_record(record.as_ref()) {
+
+            // Held until the record is stored, so that concurrent submissions
+            // by one author cannot all pass the quota check
+            let _quota_guard = ctx
+                .quotas
+                .as_ref()
+                .map(|_| ctx.quota_locks.lock(&record.author_public_key()));
+            if let Some(quotas) = &ctx.quotas {
+                match check_quota(quotas, record.as_ref(), &ctx.store, ctx.moderation.as_ref()) {
+                    Ok(None) => {}
+                    Ok(Some(result_code)) => {
+                        return Ok(Message::new_submission_result(id, result_code));
+                    }
+                    Err(store_err) => {
+                        let result_code = store_error_result_code(&store_err);
+                        logger.log_client_error(
+                            store_err,
+                            client_data.remote_address,
+                            client_data.peer,
+                        );
+                        return Ok(Message::new_submission_result(id, result_code));
+                    }
+                }
+            }
+
+            let untrusted = ctx
+                .moderation
+                .as_ref()
+                .filter(|moderation| !moderation.trusts(&record.author_public_key()));
+            if let Some(moderation) = untrusted {
+                return Ok(hold_for_review(
+                    moderation,
+                    record.as_ref(),
+                    client_data,
+                    ctx,
+                ));
+            }
+
+            let put_result = ctx.store.put_record(record.as_ref());
+            if let (Ok(PutResult::Inserted), Some(filter)) = (&put_result, &ctx.duplicates) {
+                filter.insert(&id);
+            }
+
+            match put_result {
                 Ok(PutResult::Inserted) => {
                     Ok(Message::new_submission_result(id, ResultCode::Accepted))
                 }
@@ -156,12 +350,13 @@ fn handle_submission<L: Logger>(
                     Ok(Message::new_submission_result(id, ResultCode::Duplicate))
                 }
                 Err(store_err) => {
+                    let result_code = store_error_result_code(&store_err);
                     logger.log_client_error(
                         store_err,
                         client_data.remote_address,
                         client_data.peer,
                     );
-                    Ok(Message::new_submission_result(id, ResultCode::GeneralError))
+                    Ok(Message::new_submission_result(id, result_code))
                 }
             }
         }
@@ -184,6 +379,171 @@ fn handle_submission<L: Logger>(
     }
 }
 
+/// Put a verified record from an untrusted author in the moderation queue,
+/// unless it is already published
+fn hold_for_review<L: Logger>(
+    moderation: &ModerationPolicy,
+    record: &Record,
+    client_data: &ClientData,
+    ctx: &HandlerContext<L>,
+) -> Message {
+    let id = record.id();
+    let held = match ctx.store.has_record(&id.to_reference()) {
+        Ok(true) => Ok(ResultCode::Duplicate),
+        Ok(false) => moderation
+            .hold(record)
+            .map(|_| ModerationPolicy::PENDING_CODE),
+        Err(e) => Err(e),
+    };
+    match held {
+        Ok(result_code) => Message::new_submission_result(id, result_code),
+        Err(store_err) => {
+            let result_code = store_error_result_code(&store_err);
+            ctx.logger
+                .log_client_error(store_err, client_data.remote_address, client_data.peer);
+            Message::new_submission_result(id, result_code)
+        }
+    }
+}
+
+/// Publish the held record `id`, checking it against the blocklist and quotas
+/// as a fresh submission would be, since either may have changed while it was
+/// held. Returns false if it is not held; a refused record stays held.
+pub(crate) fn release_pending<L: Logger>(
+    moderation: &ModerationPolicy,
+    id: &Id,
+    ctx: &HandlerContext<L>,
+) -> Result<bool, Error> {
+    let Some(record) = moderation.held(id)? else {
+        return Ok(false);
+    };
+    if is_blocked(ctx, record.as_ref()) {
+        return Err(InnerError::General("the held record is blocked".to_owned()).into_err());
+    }
+
+    let _quota_guard = ctx
+        .quotas
+        .as_ref()
+        .map(|_| ctx.quota_locks.lock(&record.author_public_key()));
+    // The held record is already charged to its author, so it is refused only
+    // if the quota has since been lowered below what the author uses.
+    let over_quota = match &ctx.quotas {
+        Some(quotas) => {
+            let author = record.author_public_key();
+            let len = record.as_bytes().len() as u64;
+            let mut used = quota_usage(&ctx.store, Some(moderation), &author)?;
+            used.remove(len);
+            quotas.quota_for(&author).would_exceed(used, len)
+        }
+        None => false,
+    };
+    if over_quota {
+        return Err(
+            InnerError::General("the held record's author is over quota".to_owned()).into_err(),
+        );
+    }
+
+    let released = moderation.release(&*ctx.store, id)?;
+    if let (Some(PutResult::Inserted), Some(filter)) = (released, &ctx.duplicates) {
+        filter.insert(id);
+    }
+    Ok(released.is_some())
+}
+
+fn may_read<L: Logger>(ctx: &HandlerContext<L>, record: &Record, client_data: &ClientData) -> bool {
+    ctx.read_access
+        .as_ref()
+        .is_none_or(|policy| policy.may_read(record, client_data.peer))
+}
+
+fn is_blocked<L: Logger>(ctx: &HandlerContext<L>, record: &Record) -> bool {
+    ctx.blocklist
+        .as_ref()
+        .is_some_and(|blocklist| blocklist.blocks(record))
+}
+
+/// The id of the submitted record if it is blocked. Checked before verifying
+/// the record: a forged author only gets the forger's submission refused.
+fn blocked_submission<L: Logger>(
+    ctx: &HandlerContext<L>,
+    message: &Message,
+) -> Option<mosaic_core::Id> {
+    if message.message_type() != MessageType::Submission {
+        return None;
+    }
+    let record = Record::from_bytes(&message.as_bytes()[8..]).ok()?;
+    is_blocked(ctx, record).then(|| record.id())
+}
+
+/// Cheaply recognise a re-published record before any verification: if the
+/// filter says its id may be stored and the store confirms it, return the id.
+fn find_known_duplicate(
+    filter: &DuplicateFilter,
+    message: &Message,
+    client_data: &ClientData,
+    store: &Arc<dyn Store>,
+) -> Result<Option<mosaic_core::Id>, Error> {
+    if client_data.mosaic_version.is_none() || client_data.applications.is_none() {
+        return Ok(None);
+    }
+    let Some(id) = extract_record_id(message) else {
+        return Ok(None);
+    };
+    if filter.might_contain(&id) && store.has_record(&id.to_reference())? {
+        Ok(Some(id))
+    } else {
+        Ok(None)
+    }
+}
+
+/// Returns the result code to refuse `record` with if storing or holding it
+/// would take its author over quota. Records held for review count against the
+/// quota like stored ones, so the moderation queue cannot grow without bound.
+/// Records already stored or held are let through so they are reported as
+/// duplicates. Callers hold the author's `QuotaLocks` lock until the record is
+/// stored or held.
+pub(crate) fn check_quota(
+    quotas: &QuotaPolicy,
+    record: &Record,
+    store: &Arc<dyn Store>,
+    moderation: Option<&ModerationPolicy>,
+) -> Result<Option<ResultCode>, Error> {
+    let author = record.author_public_key();
+    let used = quota_usage(store, moderation, &author)?;
+    let len = record.as_bytes().len() as u64;
+    if !quotas.quota_for(&author).would_exceed(used, len) {
+        return Ok(None);
+    }
+    let reference = record.id().to_reference();
+    if store.has_record(&reference)? {
+        return Ok(None);
+    }
+    let held = match moderation {
+        Some(moderation) => moderation.pending.has_record(&reference)?,
+        None => false,
+    };
+    if held {
+        return Ok(None);
+    }
+    Ok(Some(QuotaPolicy::RESULT_CODE))
+}
+
+/// What `author` has charged against their quota: the records stored, plus
+/// those held for review
+fn quota_usage(
+    store: &Arc<dyn Store>,
+    moderation: Option<&ModerationPolicy>,
+    author: &PublicKey,
+) -> Result<RecordTotals, Error> {
+    let mut used = store.author_stats(author)?;
+    if let Some(moderation) = moderation {
+        let held = moderation.pending.author_stats(author)?;
+        used.records += held.records;
+        used.bytes += held.bytes;
+    }
+    Ok(used)
+}
+
 fn log_validation_error<L: Logger>(
     logger: &Arc<L>,
     client_data: &ClientData,
@@ -197,6 +557,14 @@ fn log_validation_error<L: Logger>(
     );
 }
 
+/// Pick the `ResultCode` reported to a client when the store fails.
+fn store_error_result_code(error: &Error) -> ResultCode {
+    match &error.inner {
+        InnerError::Store(store_err) => store_err.result_code(),
+        _ => ResultCode::GeneralError,
+    }
+}
+
 fn extract_record_id(message: &Message) -> Option<mosaic_core::Id> {
     if message.message_type() != MessageType::Submission {
         return None;
@@ -211,57 +579,18 @@ fn extract_record_id(message: &Message) -> Option<mosaic_core::Id> {
 mod tests {
     use super::*;
 
-    use std::collections::HashMap;
     use std::sync::{Arc, Mutex};
 
-    use crate::{Logger, Store};
+    use crate::test_support::{InMemoryStore, build_record_with};
+    use crate::{
+        Fault, FaultyStore, Logger, Quota, RateLimit, ScanEntry, ScanOrder, Store, StoreError,
+        StoreErrorKind, StoreMethod, StoreStats,
+    };
     use mosaic_core::{
         EMPTY_TAG_SET, Kind, Message, MessageType, OwnedRecord, QueryId, RecordAddressData,
         RecordParts, RecordSigningData, Reference, ResultCode, SecretKey, Timestamp,
     };
 
-    #[derive(Default)]
-    struct InMemoryStore {
-        records: Mutex<HashMap<[u8; 48], OwnedRecord>>,
-    }
-
-    impl InMemoryStore {
-        fn record_count(&self) -> usize {
-            self.records.lock().unwrap().len()
-        }
-
-        fn contains(&self, id: &[u8; 48]) -> bool {
-            self.records.lock().unwrap().contains_key(id)
-        }
-    }
-
-    impl Store for InMemoryStore {
-        fn put_record(&self, record: &mosaic_core::Record) -> Result<PutResult, Error> {
-            let mut guard = self.records.lock().unwrap();
-            let id_bytes = *record.id().as_bytes();
-            if guard.contains_key(&id_bytes) {
-                Ok(PutResult::Duplicate)
-            } else {
-                let owned = OwnedRecord::from_vec(record.as_bytes().to_vec())?;
-                guard.insert(id_bytes, owned);
-                Ok(PutResult::Inserted)
-            }
-        }
-
-        fn has_record(&self, reference: &mosaic_core::Reference) -> Result<bool, Error> {
-            let target = *reference.as_bytes();
-            Ok(self.records.lock().unwrap().contains_key(&target))
-        }
-
-        fn get_record(
-            &self,
-            reference: &mosaic_core::Reference,
-        ) -> Result<Option<OwnedRecord>, Error> {
-            let target = *reference.as_bytes();
-            Ok(self.records.lock().unwrap().get(&target).cloned())
-        }
-    }
-
     #[derive(Default)]
     struct TestLogger {
         entries: Mutex<Vec<String>>,
@@ -286,8 +615,8 @@ mod tests {
 
     struct TestEnv {
         store_impl: Arc<InMemoryStore>,
-        store: Arc<dyn Store>,
         logger: Arc<TestLogger>,
+        ctx: HandlerContext<TestLogger>,
     }
 
     impl TestEnv {
@@ -295,10 +624,11 @@ mod tests {
             let store_impl = Arc::new(InMemoryStore::default());
             let store: Arc<dyn Store> = store_impl.clone();
             let logger = Arc::new(TestLogger::default());
+            let ctx = HandlerContext::new(store, logger.clone());
             Self {
                 store_impl,
-                store,
                 logger,
+                ctx,
             }
         }
     }
@@ -320,6 +650,65 @@ mod tests {
         ) -> Result<Option<OwnedRecord>, Error> {
             Ok(None)
         }
+
+        fn stats(&self) -> Result<StoreStats, Error> {
+            Ok(StoreStats::default())
+        }
+
+        fn delete_record(&self, _reference: &mosaic_core::Reference) -> Result<bool, Error> {
+            Ok(false)
+        }
+
+        fn scan(
+            &self,
+            _order: ScanOrder,
+            _after: Option<&mosaic_core::Id>,
+            _limit: usize,
+        ) -> Result<Vec<ScanEntry>, Error> {
+            Ok(Vec::new())
+        }
+    }
+
+    struct ClassifiedFailingStore(StoreErrorKind);
+
+    impl ClassifiedFailingStore {
+        fn error(&self) -> Error {
+            StoreError::new(self.0, "classified store failure").into()
+        }
+    }
+
+    impl Store for ClassifiedFailingStore {
+        fn put_record(&self, _record: &mosaic_core::Record) -> Result<PutResult, Error> {
+            Err(self.error())
+        }
+
+        fn has_record(&self, _reference: &mosaic_core::Reference) -> Result<bool, Error> {
+            Err(self.error())
+        }
+
+        fn get_record(
+            &self,
+            _reference: &mosaic_core::Reference,
+        ) -> Result<Option<OwnedRecord>, Error> {
+            Err(self.error())
+        }
+
+        fn stats(&self) -> Result<StoreStats, Error> {
+            Err(self.error())
+        }
+
+        fn delete_record(&self, _reference: &mosaic_core::Reference) -> Result<bool, Error> {
+            Err(self.error())
+        }
+
+        fn scan(
+            &self,
+            _order: ScanOrder,
+            _after: Option<&mosaic_core::Id>,
+            _limit: usize,
+        ) -> Result<Vec<ScanEntry>, Error> {
+            Err(self.error())
+        }
     }
 
     fn make_client() -> ClientData {
@@ -331,6 +720,7 @@ mod tests {
             mosaic_version: None,
             applications: None,
             closing_result: None,
+            role: Role::ReadWrite,
         }
     }
 
@@ -353,7 +743,7 @@ mod tests {
         let hello = Message::new_hello(SUPPORTED_MAJOR_VERSION, &[0]).unwrap();
         let env = TestEnv::new();
 
-        let response = handle_mosaic_message(hello, &mut client, &env.store, &env.logger)
+        let response = handle_mosaic_message(hello, &mut client, &env.ctx)
             .await
             .unwrap()
             .expect("response");
@@ -374,11 +764,11 @@ mod tests {
         let hello = Message::new_hello(SUPPORTED_MAJOR_VERSION, &[0]).unwrap();
         let env = TestEnv::new();
 
-        let _ = handle_mosaic_message(hello.clone(), &mut client, &env.store, &env.logger)
+        let _ = handle_mosaic_message(hello.clone(), &mut client, &env.ctx)
             .await
             .unwrap();
 
-        let response = handle_mosaic_message(hello, &mut client, &env.store, &env.logger)
+        let response = handle_mosaic_message(hello, &mut client, &env.ctx)
             .await
             .unwrap();
 
@@ -392,7 +782,7 @@ mod tests {
         let hello = Message::new_hello(SUPPORTED_MAJOR_VERSION + 1, &[0]).unwrap();
         let env = TestEnv::new();
 
-        let response = handle_mosaic_message(hello, &mut client, &env.store, &env.logger)
+        let response = handle_mosaic_message(hello, &mut client, &env.ctx)
             .await
             .unwrap()
             .expect("response");
@@ -413,7 +803,7 @@ mod tests {
         let malformed = unsafe { Message::from_bytes_unchecked(bytes) };
         let env = TestEnv::new();
 
-        let response = handle_mosaic_message(malformed, &mut client, &env.store, &env.logger)
+        let response = handle_mosaic_message(malformed, &mut client, &env.ctx)
             .await
             .unwrap()
             .expect("response");
@@ -431,7 +821,7 @@ mod tests {
         let hello = Message::new_hello(SUPPORTED_MAJOR_VERSION, &[]).unwrap();
         let env = TestEnv::new();
 
-        let response = handle_mosaic_message(hello, &mut client, &env.store, &env.logger)
+        let response = handle_mosaic_message(hello, &mut client, &env.ctx)
             .await
             .unwrap()
             .expect("response");
@@ -449,7 +839,7 @@ mod tests {
         let message = Message::new_submission(&record).unwrap();
         let env = TestEnv::new();
 
-        let response = handle_mosaic_message(message, &mut client, &env.store, &env.logger)
+        let response = handle_mosaic_message(message, &mut client, &env.ctx)
             .await
             .unwrap()
             .expect("response");
@@ -470,7 +860,7 @@ mod tests {
         let message = Message::new_submission(&record).unwrap();
         let env = TestEnv::new();
 
-        let response = handle_mosaic_message(message.clone(), &mut client, &env.store, &env.logger)
+        let response = handle_mosaic_message(message.clone(), &mut client, &env.ctx)
             .await
             .unwrap()
             .expect("response");
@@ -479,7 +869,7 @@ mod tests {
         assert_eq!(response.result_code(), Some(ResultCode::Accepted));
         assert!(env.store_impl.contains(record.id().as_bytes()));
 
-        let duplicate = handle_mosaic_message(message, &mut client, &env.store, &env.logger)
+        let duplicate = handle_mosaic_message(message, &mut client, &env.ctx)
             .await
             .unwrap()
             .expect("response");
@@ -488,6 +878,31 @@ mod tests {
         assert_eq!(env.store_impl.record_count(), 1);
     }
 
+    #[tokio::test]
+    async fn read_only_peers_cannot_submit() {
+        let mut client = make_client();
+        client.mosaic_version = Some(0);
+        client.applications = Some(vec![0]);
+        client.role = Role::ReadOnly;
+        let record = build_record();
+        let message = Message::new_submission(&record).unwrap();
+        let env = TestEnv::new();
+
+        let response = handle_mosaic_message(message, &mut client, &env.ctx)
+            .await
+            .unwrap()
+            .expect("response");
+
+        assert_eq!(response.message_type(), MessageType::SubmissionResult);
+        assert_eq!(response.result_code(), Some(Role::RESULT_CODE));
+        assert_eq!(env.store_impl.record_count(), 0);
+
+        let reference = record.id().to_reference();
+        let get = Message::new_get(QueryId::from_bytes([3, 0]), &[&reference]).unwrap();
+        let response = handle_get(&get, &client, &env.ctx).unwrap();
+        assert_eq!(response.result_code, ResultCode::NotFound);
+    }
+
     #[tokio::test]
     async fn submission_store_error_surfaces_as_general_error() {
         let mut client = make_client();
@@ -497,8 +912,9 @@ mod tests {
         let message = Message::new_submission(&record).unwrap();
         let store: Arc<dyn Store> = Arc::new(FailingStore);
         let logger = Arc::new(TestLogger::default());
+        let ctx = HandlerContext::new(store, logger.clone());
 
-        let response = handle_mosaic_message(message, &mut client, &store, &logger)
+        let response = handle_mosaic_message(message, &mut client, &ctx)
             .await
             .unwrap()
             .expect("response");
@@ -509,6 +925,460 @@ mod tests {
         assert_eq!(logger.entries().len(), 1);
     }
 
+    #[tokio::test]
+    async fn submission_store_out_of_space_is_temporary() {
+        let mut client = make_client();
+        client.mosaic_version = Some(0);
+        client.applications = Some(vec![0]);
+        let record = build_record();
+        let message = Message::new_submission(&record).unwrap();
+        let store: Arc<dyn Store> = Arc::new(ClassifiedFailingStore(StoreErrorKind::OutOfSpace));
+        let logger = Arc::new(TestLogger::default());
+        let ctx = HandlerContext::new(store, logger.clone());
+
+        let response = handle_mosaic_message(message, &mut client, &ctx)
+            .await
+            .unwrap()
+            .expect("response");
+
+        assert_eq!(response.message_type(), MessageType::SubmissionResult);
+        assert_eq!(response.result_code(), Some(ResultCode::TemporaryError));
+        assert_eq!(logger.entries().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn intermittent_put_failures_are_reported_per_submission() {
+        let mut client = make_client();
+        client.mosaic_version = Some(0);
+        client.applications = Some(vec![0]);
+        let faulty = Arc::new(FaultyStore::new(InMemoryStore::default(), 11).with_fault(
+            StoreMethod::PutRecord,
+            Fault::sometimes(0.5, StoreErrorKind::Transient),
+        ));
+        let store: Arc<dyn Store> = faulty.clone();
+        let logger = Arc::new(TestLogger::default());
+        let ctx = HandlerContext::new(store, logger.clone());
+
+        let mut accepted = 0;
+        for _ in 0..20 {
+            let message = Message::new_submission(&build_record()).unwrap();
+            let response = handle_mosaic_message(message, &mut client, &ctx)
+                .await
+                .unwrap()
+                .expect("response");
+            match response.result_code() {
+                Some(ResultCode::Accepted) => accepted += 1,
+                Some(ResultCode::TemporaryError) => {}
+                other => panic!("unexpected result {other:?}"),
+            }
+            assert!(client.closing_result.is_none());
+        }
+
+        assert_eq!(faulty.inner().record_count(), accepted);
+        assert_eq!(faulty.injected_failures(), 20 - accepted as u64);
+        assert_eq!(logger.entries().len(), 20 - accepted);
+    }
+
+    #[test]
+    fn rate_limited_peers_are_refused_then_closed() {
+        let mut env = TestEnv::new();
+        env.ctx.rate_limiter = Some(RateLimiter::new(RateLimitConfig {
+            writes: Some(RateLimit {
+                burst: 1,
+                per_second: 0.001,
+            }),
+            reads: Some(RateLimit {
+                burst: 1,
+                per_second: 0.001,
+            }),
+            max_violations: 2,
+        }));
+        let client = make_client();
+        let mut violations = 0;
+        let record = build_record();
+        let submission = Message::new_submission(&record).unwrap();
+        let query_id = QueryId::from_bytes([0, 9]);
+        let reference = record.id().to_reference();
+        let get = Message::new_get(query_id, &[&reference]).unwrap();
+
+        for message in [&submission, &get] {
+            assert!(matches!(
+                check_rate_limit(message, &client, &mut violations, &env.ctx),
+                RateCheck::Allowed
+            ));
+        }
+
+        let RateCheck::Refused(Some(refusal)) =
+            check_rate_limit(&submission, &client, &mut violations, &env.ctx)
+        else {
+            panic!("expected a refused submission");
+        };
+        assert_eq!(refusal.message_type(), MessageType::SubmissionResult);
+        assert_eq!(refusal.result_code(), Some(ResultCode::TooFast));
+
+        let RateCheck::Refused(Some(refusal)) =
+            check_rate_limit(&get, &client, &mut violations, &env.ctx)
+        else {
+            panic!("expected a refused get");
+        };
+        assert_eq!(refusal.message_type(), MessageType::QueryClosed);
+        assert_eq!(refusal.result_code(), Some(ResultCode::TooFast));
+
+        assert!(matches!(
+            check_rate_limit(&get, &client, &mut violations, &env.ctx),
+            RateCheck::Close
+        ));
+
+        // Messages that carry no request are never limited.
+        let hello = Message::new_hello(SUPPORTED_MAJOR_VERSION, &[0]).unwrap();
+        assert!(matches!(
+            check_rate_limit(&hello, &client, &mut violations, &env.ctx),
+            RateCheck::Allowed
+        ));
+
+        // Nor are admins.
+        client.role = Role::Admin;
+        assert!(matches!(
+            check_rate_limit(&get, &client, &mut violations, &env.ctx),
+            RateCheck::Allowed
+        ));
+    }
+
+    #[tokio::test]
+    async fn submission_over_quota_is_refused() {
+        let mut client = make_client();
+        client.mosaic_version = Some(0);
+        client.applications = Some(vec![0]);
+        let mut env = TestEnv::new();
+        env.ctx.quotas = Some(QuotaPolicy {
+            default: Quota {
+                max_bytes: None,
+                max_records: Some(1),
+            },
+            overrides: Default::default(),
+        });
+
+        let signing_key = SecretKey::generate();
+        let first = build_record_with(
+            &signing_key,
+            Kind::KEY_SCHEDULE,
+            Timestamp::now().unwrap(),
+            b"1",
+        );
+        let second = build_record_with(
+            &signing_key,
+            Kind::KEY_SCHEDULE,
+            Timestamp::now().unwrap(),
+            b"2",
+        );
+
+        let message = Message::new_submission(&first).unwrap();
+        let response = handle_mosaic_message(message.clone(), &mut client, &env.ctx)
+            .await
+            .unwrap()
+            .expect("response");
+        assert_eq!(response.result_code(), Some(ResultCode::Accepted));
+
+        let response = handle_mosaic_message(
+            Message::new_submission(&second).unwrap(),
+            &mut client,
+            &env.ctx,
+        )
+        .await
+        .unwrap()
+        .expect("response");
+        assert_eq!(response.result_code(), Some(ResultCode::TooLarge));
+        assert_eq!(env.store_impl.record_count(), 1);
+
+        // Re-publishing what is already stored is still reported as a duplicate.
+        let response = handle_mosaic_message(message, &mut client, &env.ctx)
+            .await
+            .unwrap()
+            .expect("response");
+        assert_eq!(response.result_code(), Some(ResultCode::Duplicate));
+    }
+
+    #[tokio::test]
+    async fn duplicate_filter_short_circuits_known_records() {
+        let mut client = make_client();
+        let record = build_record();
+        let fresh = build_record();
+        let mut env = TestEnv::new();
+        env.store_impl.put_record(record.as_ref()).unwrap();
+        let filter = DuplicateFilter::from_store(&*env.ctx.store, Default::default()).unwrap();
+        let message = Message::new_submission(&record).unwrap();
+
+        // Before HELLO the filter must not be consulted
+        assert_eq!(
+            find_known_duplicate(&filter, &message, &client, &env.ctx.store).unwrap(),
+            None
+        );
+
+        client.mosaic_version = Some(0);
+        client.applications = Some(vec![0]);
+        assert_eq!(
+            find_known_duplicate(&filter, &message, &client, &env.ctx.store).unwrap(),
+            Some(record.id())
+        );
+        let fresh_message = Message::new_submission(&fresh).unwrap();
+        assert_eq!(
+            find_known_duplicate(&filter, &fresh_message, &client, &env.ctx.store).unwrap(),
+            None
+        );
+
+        env.ctx.duplicates = Some(Arc::new(filter));
+        let response = handle_mosaic_message(fresh_message, &mut client, &env.ctx)
+            .await
+            .unwrap()
+            .expect("response");
+        assert_eq!(response.result_code(), Some(ResultCode::Accepted));
+        let filter = env.ctx.duplicates.as_ref().unwrap();
+        assert!(filter.might_contain(&fresh.id()));
+
+        let response = handle_mosaic_message(message, &mut client, &env.ctx)
+            .await
+            .unwrap()
+            .expect("response");
+        assert_eq!(response.result_code(), Some(ResultCode::Duplicate));
+    }
+
+    #[tokio::test]
+    async fn blocked_records_are_refused_and_hidden() {
+        let mut client = make_client();
+        client.mosaic_version = Some(0);
+        client.applications = Some(vec![0]);
+        let stored = build_record();
+        let mut env = TestEnv::new();
+        env.store_impl.put_record(stored.as_ref()).unwrap();
+
+        let dir = tempfile::tempdir().unwrap();
+        let blocklist = Blocklist::open(dir.path().join("blocklist")).unwrap();
+        blocklist
+            .block(crate::BlockEntry::Author(stored.author_public_key()))
+            .unwrap();
+        env.ctx.blocklist = Some(Arc::new(blocklist));
+
+        let message = Message::new_submission(&stored).unwrap();
+        let response = handle_mosaic_message(message, &mut client, &env.ctx)
+            .await
+            .unwrap()
+            .expect("response");
+        assert_eq!(response.result_code(), Some(Blocklist::RESULT_CODE));
+
+        let reference = stored.id().to_reference();
+        let get = Message::new_get(QueryId::from_bytes([0, 6]), &[&reference]).unwrap();
+        let response = handle_get(&get, &client, &env.ctx).unwrap();
+        assert!(response.records.is_empty());
+        assert_eq!(response.result_code, ResultCode::NotFound);
+    }
+
+    #[test]
+    fn restricted_records_are_not_found_by_other_peers() {
+        let mut client = make_client();
+        client.mosaic_version = Some(0);
+        client.applications = Some(vec![0]);
+        let author = SecretKey::generate();
+        let private = build_record_with(
+            &author,
+            Kind::KEY_SCHEDULE,
+            Timestamp::now().unwrap(),
+            b"private",
+        );
+        let mut env = TestEnv::new();
+        env.store_impl.put_record(private.as_ref()).unwrap();
+        env.ctx.read_access = Some(ReadAccessPolicy {
+            restricted_kinds: [Kind::KEY_SCHEDULE].into_iter().collect(),
+            ..Default::default()
+        });
+
+        let reference = private.id().to_reference();
+        let get = Message::new_get(QueryId::from_bytes([0, 8]), &[&reference]).unwrap();
+        let response = handle_get(&get, &client, &env.ctx).unwrap();
+        assert_eq!(response.result_code, ResultCode::NotFound);
+
+        client.peer = Some(SecretKey::generate().public());
+        let response = handle_get(&get, &client, &env.ctx).unwrap();
+        assert_eq!(response.result_code, ResultCode::NotFound);
+
+        client.peer = Some(author.public());
+        let response = handle_get(&get, &client, &env.ctx).unwrap();
+        assert_eq!(response.result_code, ResultCode::Success);
+        assert_eq!(response.records.len(), 1);
+    }
+
+    #[test]
+    fn private_mode_refuses_reads_from_non_members() {
+        let mut client = make_client();
+        client.mosaic_version = Some(0);
+        client.applications = Some(vec![0]);
+        let member = SecretKey::generate().public();
+        let mut env = TestEnv::new();
+        env.ctx.read_members = Some(Arc::new(ReadMembers::new([])));
+
+        let reference = build_record().id().to_reference();
+        let get = Message::new_get(QueryId::from_bytes([0, 10]), &[&reference]).unwrap();
+        let refusal = refuse_non_member(&get, &client, &env.ctx).expect("anonymous refused");
+        assert_eq!(refusal.message_type(), MessageType::QueryClosed);
+        assert_eq!(refusal.result_code(), Some(ReadMembers::RESULT_CODE));
+
+        client.peer = Some(member);
+        assert!(refuse_non_member(&get, &client, &env.ctx).is_some());
+        env.ctx.read_members.as_ref().unwrap().add(member);
+        assert!(refuse_non_member(&get, &client, &env.ctx).is_none());
+
+        // Submissions and HELLO are not reads
+        client.peer = None;
+        let submission = Message::new_submission(&build_record()).unwrap();
+        assert!(refuse_non_member(&submission, &client, &env.ctx).is_none());
+        let hello = Message::new_hello(0, &[0]).unwrap();
+        assert!(refuse_non_member(&hello, &client, &env.ctx).is_none());
+
+        // Admins read without being members
+        client.peer = Some(SecretKey::generate().public());
+        client.role = Role::Admin;
+        assert!(refuse_non_member(&get, &client, &env.ctx).is_none());
+    }
+
+    #[tokio::test]
+    async fn untrusted_submissions_are_held_for_review() {
+        let mut client = make_client();
+        client.mosaic_version = Some(0);
+        client.applications = Some(vec![0]);
+        let trusted_key = SecretKey::generate();
+        let trusted = build_record_with(
+            &trusted_key,
+            Kind::KEY_SCHEDULE,
+            Timestamp::now().unwrap(),
+            b"trusted",
+        );
+        let untrusted = build_record();
+        let pending = Arc::new(InMemoryStore::default());
+        let mut env = TestEnv::new();
+        env.ctx.moderation = Some(ModerationPolicy::new(
+            pending.clone(),
+            [trusted_key.public()],
+        ));
+
+        let message = Message::new_submission(&untrusted).unwrap();
+        let response = handle_mosaic_message(message, &mut client, &env.ctx)
+            .await
+            .unwrap()
+            .expect("response");
+        assert_eq!(response.result_code(), Some(ResultCode::NoConsumers));
+        assert!(pending.contains(untrusted.id().as_bytes()));
+        assert_eq!(env.store_impl.record_count(), 0);
+
+        let reference = untrusted.id().to_reference();
+        let get = Message::new_get(QueryId::from_bytes([0, 7]), &[&reference]).unwrap();
+        let response = handle_get(&get, &client, &env.ctx).unwrap();
+        assert_eq!(response.result_code, ResultCode::NotFound);
+
+        let message = Message::new_submission(&trusted).unwrap();
+        let response = handle_mosaic_message(message, &mut client, &env.ctx)
+            .await
+            .unwrap()
+            .expect("response");
+        assert_eq!(response.result_code(), Some(ResultCode::Accepted));
+        assert!(env.store_impl.contains(trusted.id().as_bytes()));
+    }
+
+    #[tokio::test]
+    async fn held_submissions_count_against_the_quota() {
+        let mut client = make_client();
+        client.mosaic_version = Some(0);
+        client.applications = Some(vec![0]);
+        let signing_key = SecretKey::generate();
+        let records: Vec<OwnedRecord> = (0..2)
+            .map(|i| {
+                build_record_with(
+                    &signing_key,
+                    Kind::KEY_SCHEDULE,
+                    Timestamp::now().unwrap(),
+                    format!("held {i}").as_bytes(),
+                )
+            })
+            .collect();
+        let pending = Arc::new(InMemoryStore::default());
+        let mut env = TestEnv::new();
+        env.ctx.moderation = Some(ModerationPolicy::new(pending.clone(), []));
+        env.ctx.quotas = Some(QuotaPolicy {
+            default: Quota {
+                max_bytes: None,
+                max_records: Some(1),
+            },
+            overrides: Default::default(),
+        });
+
+        // Resubmitting a held record is not charged twice
+        let expected = [
+            (&records[0], ResultCode::NoConsumers),
+            (&records[1], QuotaPolicy::RESULT_CODE),
+            (&records[0], ResultCode::NoConsumers),
+        ];
+        for (record, code) in expected {
+            let message = Message::new_submission(record).unwrap();
+            let response = handle_mosaic_message(message, &mut client, &env.ctx)
+                .await
+                .unwrap()
+                .expect("response");
+            assert_eq!(response.result_code(), Some(code));
+        }
+        assert_eq!(pending.record_count(), 1);
+    }
+
+    #[test]
+    fn release_rechecks_blocklist_and_quota() {
+        let signing_key = SecretKey::generate();
+        let records: Vec<OwnedRecord> = (0..3)
+            .map(|i| {
+                build_record_with(
+                    &signing_key,
+                    Kind::KEY_SCHEDULE,
+                    Timestamp::now().unwrap(),
+                    format!("held {i}").as_bytes(),
+                )
+            })
+            .collect();
+        let blocked = build_record();
+        let pending = Arc::new(InMemoryStore::default());
+        let moderation = ModerationPolicy::new(pending.clone(), []);
+        for record in records.iter().chain([&blocked]) {
+            moderation.hold(record.as_ref()).unwrap();
+        }
+
+        let quota = |max_records| {
+            Some(QuotaPolicy {
+                default: Quota {
+                    max_bytes: None,
+                    max_records: Some(max_records),
+                },
+                overrides: Default::default(),
+            })
+        };
+        let mut env = TestEnv::new();
+        env.ctx.quotas = quota(3);
+        let dir = tempfile::tempdir().unwrap();
+        let blocklist = Blocklist::open(dir.path().join("blocklist")).unwrap();
+        blocklist
+            .block(crate::BlockEntry::Record(blocked.id()))
+            .unwrap();
+        env.ctx.blocklist = Some(Arc::new(blocklist));
+
+        assert!(release_pending(&moderation, &blocked.id(), &env.ctx).is_err());
+        assert!(release_pending(&moderation, &records[0].id(), &env.ctx).unwrap());
+        assert!(release_pending(&moderation, &records[1].id(), &env.ctx).unwrap());
+        assert!(!release_pending(&moderation, &records[0].id(), &env.ctx).unwrap());
+
+        // The quota was lowered while the last record was held
+        env.ctx.quotas = quota(2);
+        assert!(release_pending(&moderation, &records[2].id(), &env.ctx).is_err());
+
+        // Refused records stay held for the operator to reject
+        assert_eq!(env.store_impl.record_count(), 2);
+        assert!(pending.contains(blocked.id().as_bytes()));
+        assert!(pending.contains(records[2].id().as_bytes()));
+    }
+
     #[tokio::test]
     async fn submission_with_unreadable_record_triggers_closing() {
         let mut client = make_client();
@@ -526,7 +1396,7 @@ mod tests {
 
         let env = TestEnv::new();
 
-        let response = handle_mosaic_message(corrupted, &mut client, &env.store, &env.logger)
+        let response = handle_mosaic_message(corrupted, &mut client, &env.ctx)
             .await
             .unwrap()
             .expect("closing response");
@@ -548,7 +1418,7 @@ mod tests {
         let get_message = Message::new_get(query_id, &[&reference]).unwrap();
         let client = make_client();
 
-        let response = handle_get(&get_message, &client, &env.store).unwrap();
+        let response = handle_get(&get_message, &client, &env.ctx).unwrap();
         assert_eq!(response.result_code, ResultCode::Invalid);
         assert!(response.records.is_empty());
     }
@@ -568,7 +1438,7 @@ mod tests {
         client.mosaic_version = Some(0);
         client.applications = Some(vec![0]);
 
-        let response = handle_get(&get_message, &client, &env.store).unwrap();
+        let response = handle_get(&get_message, &client, &env.ctx).unwrap();
         assert_eq!(response.result_code, ResultCode::Success);
         assert_eq!(response.records.len(), 1);
         assert_eq!(response.records[0].as_bytes(), record.as_bytes());
@@ -586,8 +1456,52 @@ mod tests {
         let query_id = QueryId::from_bytes([0, 3]);
         let get_message = Message::new_get(query_id, &[&reference]).unwrap();
 
-        let response = handle_get(&get_message, &client, &env.store).unwrap();
+        let response = handle_get(&get_message, &client, &env.ctx).unwrap();
         assert_eq!(response.result_code, ResultCode::NotFound);
         assert!(response.records.is_empty());
     }
+
+    #[test]
+    fn get_during_read_outage_is_temporary() {
+        let faulty = Arc::new(FaultyStore::new(InMemoryStore::default(), 5));
+        let record = build_record();
+        faulty.put_record(record.as_ref()).unwrap();
+        let store: Arc<dyn Store> = faulty.clone();
+        let ctx = HandlerContext::new(store, Arc::new(TestLogger::default()));
+        let mut client = make_client();
+        client.mosaic_version = Some(0);
+        client.applications = Some(vec![0]);
+        let reference = record.id().to_reference();
+        let get_message = Message::new_get(QueryId::from_bytes([0, 5]), &[&reference]).unwrap();
+
+        faulty.begin_outage(&[StoreMethod::GetRecord], StoreErrorKind::Transient);
+        let response = handle_get(&get_message, &client, &ctx).unwrap();
+        assert_eq!(response.result_code, ResultCode::TemporaryError);
+        assert!(response.records.is_empty());
+
+        faulty.end_outage();
+        let response = handle_get(&get_message, &client, &ctx).unwrap();
+        assert_eq!(response.result_code, ResultCode::Success);
+        assert_eq!(response.records.len(), 1);
+    }
+
+    #[test]
+    fn get_store_corruption_closes_query_with_persistent_error() {
+        let store: Arc<dyn Store> = Arc::new(ClassifiedFailingStore(StoreErrorKind::Corruption));
+        let logger = Arc::new(TestLogger::default());
+        let mut client = make_client();
+        client.mosaic_version = Some(0);
+        client.applications = Some(vec![0]);
+
+        let record = build_record();
+        let reference = record.id().to_reference();
+        let query_id = QueryId::from_bytes([0, 4]);
+        let get_message = Message::new_get(query_id, &[&reference]).unwrap();
+
+        let ctx = HandlerContext::new(store, logger.clone());
+        let response = handle_get(&get_message, &client, &ctx).unwrap();
+        assert_eq!(response.result_code, ResultCode::PersistentError);
+        assert!(response.records.is_empty());
+        assert_eq!(logger.entries().len(), 1);
+    }
 }
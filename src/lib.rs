@@ -1,6 +1,33 @@
 //! A Mosaic Server library
 //!
 //! NOTE: You must use Tokio as the async runtime in your `main()`
+//!
+//! Storage is pluggable via the `Store` trait. The LMDB backend (`LmdbStore`) is
+//! provided behind the default `lmdb` feature; disable default features to build
+//! against your own `Store` implementation only. A single-file SQLite backend
+//! (`SqliteStore`) is available behind the `sqlite` feature.
+
+mod access;
+pub use access::{ReadAccessPolicy, ReadMembers};
+
+mod archive;
+pub use archive::{ExportReport, ImportReport, export_archive, import_archive};
+
+mod authorize;
+pub use authorize::{Authorization, PeerAuthorizer, Role};
+
+mod backup;
+use backup::run_backups;
+pub use backup::{BackupPolicy, backup_rotated};
+
+mod blocklist;
+pub use blocklist::{BlockEntry, Blocklist};
+
+mod cache;
+pub use cache::{CacheConfig, CacheStats, CachedStore};
+
+mod cidr;
+pub use cidr::{Cidr, CidrApprover, CidrRules};
 
 mod client;
 pub use client::ClientData;
@@ -8,24 +35,83 @@ pub use client::ClientData;
 mod config;
 pub use config::{Logger, ServerConfig};
 
+mod duplicate_filter;
+pub use duplicate_filter::{DuplicateFilter, DuplicateFilterConfig};
+
 mod error;
 pub use error::{Error, InnerError};
 
+mod faulty;
+pub use faulty::{Fault, FaultyStore, StoreMethod};
+
+mod fsck;
+pub use fsck::{FsckProblem, FsckReport, check_store};
+
 mod handler;
-use handler::{handle_get, handle_mosaic_message};
+use handler::{
+    HandlerContext, RateCheck, check_rate_limit, handle_get, handle_mosaic_message,
+    refuse_non_member, release_pending,
+};
+
+mod hex;
+
+mod limits;
+use limits::{ConnectionGuard, ConnectionLimiter};
+pub use limits::{ConnectionLimits, ConnectionRejection, ConnectionStats};
+
+#[cfg(feature = "lmdb")]
+mod lmdb;
+#[cfg(feature = "lmdb")]
+pub use lmdb::{LmdbStore, StoreUsage};
+
+mod mirror;
+pub use mirror::{Divergence, MirroredStore};
+
+mod moderation;
+pub use moderation::ModerationPolicy;
+
+mod pow;
+pub use pow::{ProofOfWork, work_bits};
+
+mod quota;
+pub use quota::{Quota, QuotaPolicy, QuotaUsage};
+
+mod rate_limit;
+use rate_limit::RateLimiter;
+pub use rate_limit::{RateLimit, RateLimitConfig};
+
+mod retention;
+use retention::run_retention;
+pub use retention::{RetentionPolicy, RetentionReason, RetentionReport, collect_garbage};
+
+#[cfg(feature = "sqlite")]
+mod sqlite;
+#[cfg(feature = "sqlite")]
+pub use sqlite::SqliteStore;
 
 mod store;
-pub use store::{LmdbStore, PutResult, Store};
+pub use store::{
+    PutResult, RecordTotals, ScanEntry, ScanOrder, Store, StoreError, StoreErrorKind, StoreStats,
+};
+
+mod timeouts;
+use timeouts::within;
+pub use timeouts::{TimeoutKind, Timeouts};
 
 mod validation;
 pub use validation::{SubmissionValidationError, validate_submission};
 
+#[cfg(test)]
+mod test_support;
+
+use std::path::PathBuf;
 use std::sync::Arc;
 
 // use dashmap::DashMap;
 use tokio::sync::SetOnce;
+use tokio::time::Instant;
 
-use mosaic_core::{Message, MessageType, ResultCode};
+use mosaic_core::{Id, Message, MessageType, OwnedRecord, PublicKey, ResultCode};
 use mosaic_net::Server as QuicServer;
 use mosaic_net::ServerConfig as QuicServerConfig;
 use mosaic_net::{Approver, IncomingClient};
@@ -36,9 +122,17 @@ pub struct Server<A: Approver, L: Logger> {
 
     approver: Arc<A>,
 
-    logger: Arc<L>,
+    // Store, logger and policies shared with every client task
+    context: Arc<HandlerContext<L>>,
+
+    retention: Option<Arc<RetentionPolicy>>,
+
+    backups: Option<Arc<BackupPolicy>>,
+
+    // Counts open connections against the configured caps
+    connections: Option<Arc<ConnectionLimiter>>,
 
-    store: Arc<dyn Store>,
+    timeouts: Timeouts,
 
     // Connected clients
     // client_map: Arc<DashMap<SocketAddr, ClientData>>,
@@ -59,6 +153,19 @@ impl<A: Approver + 'static, L: Logger + 'static> Server<A, L> {
             approver,
             logger,
             store,
+            retention,
+            quotas,
+            duplicate_filter,
+            backups,
+            rate_limits,
+            connection_limits,
+            timeouts,
+            peer_authorizer,
+            proof_of_work,
+            blocklist,
+            moderation,
+            read_access,
+            read_members,
         } = config;
 
         let quic_server = {
@@ -66,33 +173,95 @@ impl<A: Approver + 'static, L: Logger + 'static> Server<A, L> {
             QuicServer::new(quic_server_config)?
         };
 
+        let duplicates = match duplicate_filter {
+            Some(filter_config) => Some(Arc::new(DuplicateFilter::from_store(
+                &*store,
+                filter_config,
+            )?)),
+            None => None,
+        };
+
+        let mut context = HandlerContext::new(store, Arc::new(logger));
+        context.quotas = quotas;
+        context.duplicates = duplicates;
+        context.rate_limiter = rate_limits.map(RateLimiter::new);
+        context.authorizer = peer_authorizer;
+        context.pow = proof_of_work.map(Arc::new);
+        context.moderation = moderation;
+        context.read_access = read_access;
+        context.read_members = read_members.map(Arc::new);
+        context.blocklist = match blocklist {
+            Some(path) => Some(Arc::new(Blocklist::open(path)?)),
+            None => None,
+        };
+
         Ok(Arc::new(Server {
             quic_server: Arc::new(quic_server),
             approver: Arc::new(approver),
-            logger: Arc::new(logger),
-            store,
+            context: Arc::new(context),
+            retention: retention.map(Arc::new),
+            backups: backups.map(Arc::new),
+            connections: connection_limits.map(|limits| Arc::new(ConnectionLimiter::new(limits))),
+            timeouts,
             // client_map: Arc::new(DashMap::new()),
             shutting_down: Arc::new(SetOnce::new()),
             shutdown_complete: Arc::new(SetOnce::new()),
         }))
     }
 
+    /// The approver deciding which clients may connect
+    pub fn approver(&self) -> &A {
+        &self.approver
+    }
+
     /// Run the Mosaic server
     pub async fn run(&self) -> Result<(), Error> {
         // TBD: Start WebSocket Server
         // TBD: Start TCP Server
 
+        let retention_task = self.retention.clone().map(|policy| {
+            tokio::spawn(run_retention(
+                self.context.store.clone(),
+                policy,
+                self.context.duplicates.clone(),
+                self.context.logger.clone(),
+                self.shutting_down.clone(),
+            ))
+        });
+
+        let backup_task = self.backups.clone().map(|policy| {
+            tokio::spawn(run_backups(
+                self.context.store.clone(),
+                policy,
+                self.context.logger.clone(),
+                self.shutting_down.clone(),
+            ))
+        });
+
         loop {
             tokio::select! {
                 v = self.quic_server.accept() => {
                     match v {
                         Ok(quic_client) => {
+                            let admission = match &self.connections {
+                                Some(limiter) => {
+                                    let ip = quic_client.inner().remote_address().ip();
+                                    limiter.try_acquire(ip).map(Some)
+                                }
+                                None => Ok(None),
+                            };
+                            // Over-limit connections are refused before their
+                            // handshake, so a flood costs no TLS work or task
+                            let Ok(admission) = admission else {
+                                quic_client.into_inner().refuse();
+                                continue;
+                            };
                             let approver2 = self.approver.clone();
-                            let logger2 = self.logger.clone();
                             // let client_map2 = self.client_map.clone();
-                            let store2 = self.store.clone();
+                            let context2 = self.context.clone();
+                            let timeouts = self.timeouts;
                             tokio::spawn(async move {
-                                handle_quic_client(quic_client, approver2, logger2, store2).await;
+                                handle_quic_client(quic_client, approver2, context2, admission, timeouts).await;
                             });
                         },
                         Err(e) => {
@@ -103,6 +272,9 @@ impl<A: Approver + 'static, L: Logger + 'static> Server<A, L> {
                 },
                 v = self.shutting_down.wait() => {
                     self.quic_server.shut_down(*v, b"Shutting down").await;
+                    for task in [retention_task, backup_task].into_iter().flatten() {
+                        let _ = task.await;
+                    }
                     let _ = self.shutdown_complete.set(());
                     break;
                 }
@@ -112,6 +284,117 @@ impl<A: Approver + 'static, L: Logger + 'static> Server<A, L> {
         Ok(())
     }
 
+    /// Statistics about the records held by the server's store
+    pub fn store_stats(&self) -> Result<StoreStats, Error> {
+        self.context.store.stats()
+    }
+
+    /// Write a consistent snapshot of the server's store into the directory
+    /// `path` while the server keeps running
+    pub async fn backup_to(&self, path: impl Into<PathBuf>) -> Result<(), Error> {
+        let store = self.context.store.clone();
+        let path = path.into();
+        tokio::task::spawn_blocking(move || store.backup_to(&path)).await?
+    }
+
+    /// Counters of open and refused connections, or `None` if connection
+    /// limits are not enabled
+    pub fn connection_stats(&self) -> Option<ConnectionStats> {
+        self.connections.as_ref().map(|limiter| limiter.stats())
+    }
+
+    /// Block an author or record: submissions of it are refused and stored
+    /// copies are no longer served. Returns false if it was already blocked.
+    ///
+    /// This writes and syncs the blocklist file, blocking the calling thread
+    /// and briefly the connection handlers; from async code, call it inside
+    /// `tokio::task::spawn_blocking`.
+    pub fn block(&self, entry: BlockEntry) -> Result<bool, Error> {
+        self.blocklist()?.block(entry)
+    }
+
+    /// Lift a block. Returns false if `entry` was not blocked. Blocks on the
+    /// disk like `block`.
+    pub fn unblock(&self, entry: &BlockEntry) -> Result<bool, Error> {
+        self.blocklist()?.unblock(entry)
+    }
+
+    /// Every blocked author and record
+    pub fn blocked(&self) -> Result<Vec<BlockEntry>, Error> {
+        Ok(self.blocklist()?.entries())
+    }
+
+    /// Delete every blocked record from the store, returning their ids
+    pub async fn purge_blocked(&self) -> Result<Vec<Id>, Error> {
+        let blocklist = self.blocklist()?.clone();
+        let store = self.context.store.clone();
+        let purged = tokio::task::spawn_blocking(move || blocklist.purge(&*store)).await??;
+        if let Some(filter) = &self.context.duplicates {
+            for id in &purged {
+                filter.remove(id);
+            }
+        }
+        Ok(purged)
+    }
+
+    /// Up to `limit` submissions held for review, oldest first, starting just
+    /// after the record with id `after`
+    pub fn pending(&self, after: Option<&Id>, limit: usize) -> Result<Vec<OwnedRecord>, Error> {
+        self.moderation()?.list(after, limit)
+    }
+
+    /// Publish the held submission `id`. Returns false if it is not held.
+    ///
+    /// The record must still pass the blocklist and its author's quota, which
+    /// may have changed since it was submitted; if it does not, an error is
+    /// returned and it stays held.
+    pub fn release_pending(&self, id: &Id) -> Result<bool, Error> {
+        release_pending(self.moderation()?, id, &self.context)
+    }
+
+    /// Discard the held submission `id`. Returns false if it is not held.
+    pub fn reject_pending(&self, id: &Id) -> Result<bool, Error> {
+        self.moderation()?.reject(id)
+    }
+
+    fn moderation(&self) -> Result<&ModerationPolicy, Error> {
+        self.context.moderation.as_ref().ok_or_else(|| {
+            InnerError::General("moderation is not configured".to_owned()).into_err()
+        })
+    }
+
+    fn blocklist(&self) -> Result<&Arc<Blocklist>, Error> {
+        self.context
+            .blocklist
+            .as_ref()
+            .ok_or_else(|| InnerError::General("no blocklist is configured".to_owned()).into_err())
+    }
+
+    /// The members allowed to read in private server mode, if it is enabled.
+    /// Members can be added and removed while the server runs.
+    pub fn read_members(&self) -> Option<&ReadMembers> {
+        self.context.read_members.as_deref()
+    }
+
+    /// The proof-of-work policy, if one is configured. Its difficulty can be
+    /// changed while the server runs.
+    pub fn proof_of_work(&self) -> Option<&ProofOfWork> {
+        self.context.pow.as_deref()
+    }
+
+    /// How much `author` has stored compared to their quota, or `None` if
+    /// quotas are not enabled
+    pub fn quota_usage(&self, author: &PublicKey) -> Result<Option<QuotaUsage>, Error> {
+        let Some(quotas) = &self.context.quotas else {
+            return Ok(None);
+        };
+        let used = self.context.store.author_stats(author)?;
+        Ok(Some(QuotaUsage {
+            used,
+            quota: quotas.quota_for(author),
+        }))
+    }
+
     /// True if the server is shutting down
     pub fn is_shutting_down(&self) -> bool {
         self.shutting_down.initialized()
@@ -131,53 +414,138 @@ impl<A: Approver + 'static, L: Logger + 'static> Server<A, L> {
 async fn handle_quic_client<A: Approver, L: Logger>(
     client: IncomingClient,
     approver: Arc<A>,
-    logger: Arc<L>,
-    store: Arc<dyn Store>,
+    context: Arc<HandlerContext<L>>,
+    mut admission: Option<ConnectionGuard>,
+    timeouts: Timeouts,
     // client_map: Arc<DashMap<SocketAddr, ClientData>>,
 ) {
+    let logger = &context.logger;
     let remote_address = client.inner().remote_address();
-
-    let connection: mosaic_net::ClientConnection = match client.accept(&*approver).await {
-        Ok(c) => c,
-        Err(e) => {
+    let connected_at = Instant::now();
+
+    let accepting = within(
+        timeouts.channel_deadline(connected_at, false, connected_at),
+        client.accept(&*approver),
+    );
+    let connection: mosaic_net::ClientConnection = match accepting.await {
+        Ok(Ok(c)) => c,
+        Ok(Err(e)) => {
             logger.log_client_error(e.into(), remote_address, None);
             return;
         }
+        Err(kind) => {
+            logger.log_client_error(InnerError::Timeout(kind).into_err(), remote_address, None);
+            return;
+        }
     };
 
     let peer = connection.peer();
 
+    // The per-key limit needs the key the handshake revealed. The guard keeps
+    // this connection counted until the function returns.
+    let admitted = match (&mut admission, peer) {
+        (Some(guard), Some(peer)) => guard.add_peer(peer),
+        _ => Ok(()),
+    };
+    if let Err(rejection) = admitted {
+        let code = ConnectionLimits::RESULT_CODE;
+        connection.close(code.to_u8().into(), rejection.reason());
+        return;
+    }
+
+    let role = match &context.authorizer {
+        Some(authorizer) => match authorizer.authorize(peer, remote_address) {
+            Authorization::Grant(role) => role,
+            Authorization::Reject(code) => {
+                connection.close(code.to_u8().into(), b"not authorized");
+                return;
+            }
+        },
+        None => Role::ReadWrite,
+    };
+
     let mut client_data = ClientData {
         remote_address,
         peer,
         mosaic_version: None,
         applications: None,
         closing_result: None,
+        role,
     };
 
     const NO_CHANNEL: &[u8] = b"No QUIC channel";
 
     let close_reason;
+    let mut rate_violations = 0;
 
     loop {
-        // Get the next channel from the client
-        let mut channel = match connection.next_channel().await {
-            Ok(c) => c,
-            Err(e) => {
+        // Get the next channel from the client, allowing until the end of the
+        // handshake before HELLO and the idle timeout after
+        let handshaken = client_data.mosaic_version.is_some();
+        let deadline = timeouts.channel_deadline(connected_at, handshaken, Instant::now());
+        let mut channel = match within(deadline, connection.next_channel()).await {
+            Ok(Ok(c)) => c,
+            Ok(Err(e)) => {
                 logger.log_client_error(e.into(), remote_address, peer);
                 return;
             }
+            Err(kind) => {
+                logger.log_client_error(InnerError::Timeout(kind).into_err(), remote_address, peer);
+                connection.close(kind.result_code().to_u8().into(), kind.reason());
+                return;
+            }
         };
 
         // Get the next message from the channel
-        match channel.recv().await {
+        let deadline = timeouts.message_deadline(connected_at, handshaken, Instant::now());
+        let received = match within(deadline, channel.recv()).await {
+            Ok(received) => received,
+            Err(kind) => {
+                logger.log_client_error(InnerError::Timeout(kind).into_err(), remote_address, peer);
+                let code = kind.result_code();
+                if let Err(e) = channel.send(Message::new_closing(code)).await {
+                    logger.log_client_error(e.into(), remote_address, peer);
+                }
+                connection.close(code.to_u8().into(), kind.reason());
+                return;
+            }
+        };
+        match received {
             Ok(None) => {
                 close_reason = NO_CHANNEL;
                 break;
             }
             Ok(Some(message)) => {
+                match check_rate_limit(&message, &client_data, &mut rate_violations, &context) {
+                    RateCheck::Allowed => {}
+                    RateCheck::Refused(Some(refusal)) => {
+                        if let Err(e) = channel.send(refusal).await {
+                            logger.log_client_error(e.into(), remote_address, peer);
+                            return;
+                        }
+                        continue;
+                    }
+                    RateCheck::Refused(None) => continue,
+                    RateCheck::Close => {
+                        let code = RateLimitConfig::RESULT_CODE;
+                        if let Err(e) = channel.send(Message::new_closing(code)).await {
+                            logger.log_client_error(e.into(), remote_address, peer);
+                        }
+                        connection.close(code.to_u8().into(), b"rate limited");
+                        return;
+                    }
+                }
+
+                if let Some(refusal) = refuse_non_member(&message, &client_data, &context) {
+                    if let Err(e) = channel.send(refusal).await {
+                        logger.log_client_error(e.into(), remote_address, peer);
+                        return;
+                    }
+                    continue;
+                }
+
                 if message.message_type() == MessageType::Get {
-                    match handle_get(&message, &client_data, &store) {
+                    match handle_get(&message, &client_data, &context) {
                         Ok(get_response) => {
                             for record in &get_response.records {
                                 let record_msg = match Message::new_record(
@@ -214,7 +582,7 @@ async fn handle_quic_client<A: Approver, L: Logger>(
                     continue;
                 }
 
-                match handle_mosaic_message(message, &mut client_data, &store, &logger).await {
+                match handle_mosaic_message(message, &mut client_data, &context).await {
                     Ok(Some(response_message)) => {
                         let response_type = response_message.message_type();
                         let response_code = response_message.result_code();
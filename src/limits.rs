@@ -0,0 +1,302 @@
+use std::fmt;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+
+use dashmap::DashMap;
+use mosaic_core::{PublicKey, ResultCode};
+
+/// Caps on concurrent connections. `None` leaves that dimension unlimited.
+///
+/// The total, per-IP and per-prefix limits are checked as a connection
+/// arrives, and one over them is refused before its QUIC and TLS handshake.
+/// The per-key limit can only be checked once the handshake has revealed the
+/// peer's key; a connection over it is closed with `RESULT_CODE`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConnectionLimits {
+    /// Connections open at once across all peers
+    pub max_total: Option<usize>,
+
+    /// Connections from a single IP address
+    pub max_per_ip: Option<usize>,
+
+    /// Connections from a single network prefix (see the prefix lengths below)
+    pub max_per_prefix: Option<usize>,
+
+    /// Connections authenticated as a single public key
+    pub max_per_peer: Option<usize>,
+
+    /// Prefix length grouping IPv4 addresses for `max_per_prefix`
+    pub ipv4_prefix_len: u8,
+
+    /// Prefix length grouping IPv6 addresses for `max_per_prefix`
+    pub ipv6_prefix_len: u8,
+}
+
+impl ConnectionLimits {
+    /// Result code a connection is closed with when it hits the per-key limit
+    pub const RESULT_CODE: ResultCode = ResultCode::TemporaryError;
+}
+
+impl Default for ConnectionLimits {
+    fn default() -> ConnectionLimits {
+        ConnectionLimits {
+            max_total: Some(10_000),
+            max_per_ip: Some(32),
+            max_per_prefix: Some(256),
+            max_per_peer: Some(16),
+            ipv4_prefix_len: 24,
+            ipv6_prefix_len: 64,
+        }
+    }
+}
+
+/// The limit that caused a connection to be refused
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConnectionRejection {
+    /// `max_total` connections were already open
+    Total,
+
+    /// `max_per_ip` connections were already open from the peer's address
+    PerIp,
+
+    /// `max_per_prefix` connections were already open from the peer's network
+    PerPrefix,
+
+    /// `max_per_peer` connections were already authenticated as the peer's key
+    PerPeer,
+}
+
+impl ConnectionRejection {
+    /// Reason given to the peer when its connection is closed
+    #[must_use]
+    pub fn reason(&self) -> &'static [u8] {
+        match self {
+            ConnectionRejection::Total => b"server has too many connections",
+            ConnectionRejection::PerIp => b"too many connections from this address",
+            ConnectionRejection::PerPrefix => b"too many connections from this network",
+            ConnectionRejection::PerPeer => b"too many connections for this key",
+        }
+    }
+}
+
+impl fmt::Display for ConnectionRejection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&String::from_utf8_lossy(self.reason()))
+    }
+}
+
+/// Counters of open and refused connections
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ConnectionStats {
+    /// Connections currently open
+    pub active: usize,
+
+    /// Connections refused because the server was full
+    pub rejected_total: u64,
+
+    /// Connections refused by the per-IP limit
+    pub rejected_per_ip: u64,
+
+    /// Connections refused by the per-prefix limit
+    pub rejected_per_prefix: u64,
+
+    /// Connections refused by the per-key limit
+    pub rejected_per_peer: u64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum Slot {
+    Ip(IpAddr),
+    Prefix(IpAddr),
+    Peer(PublicKey),
+}
+
+/// Counts open connections and enforces `ConnectionLimits`
+pub(crate) struct ConnectionLimiter {
+    limits: ConnectionLimits,
+    counts: DashMap<Slot, usize>,
+    active: AtomicUsize,
+    rejected: [AtomicU64; 4],
+}
+
+impl ConnectionLimiter {
+    pub fn new(limits: ConnectionLimits) -> ConnectionLimiter {
+        ConnectionLimiter {
+            limits,
+            counts: DashMap::new(),
+            active: AtomicUsize::new(0),
+            rejected: Default::default(),
+        }
+    }
+
+    pub fn stats(&self) -> ConnectionStats {
+        let rejected = |r: ConnectionRejection| self.rejected[r as usize].load(Ordering::Relaxed);
+        ConnectionStats {
+            active: self.active.load(Ordering::Relaxed),
+            rejected_total: rejected(ConnectionRejection::Total),
+            rejected_per_ip: rejected(ConnectionRejection::PerIp),
+            rejected_per_prefix: rejected(ConnectionRejection::PerPrefix),
+            rejected_per_peer: rejected(ConnectionRejection::PerPeer),
+        }
+    }
+
+    /// Admit a new connection from `ip`. The connection counts against the
+    /// limits until the returned guard is dropped.
+    pub fn try_acquire(
+        self: &Arc<Self>,
+        ip: IpAddr,
+    ) -> Result<ConnectionGuard, ConnectionRejection> {
+        let previous = self.active.fetch_add(1, Ordering::AcqRel);
+        let mut guard = ConnectionGuard {
+            limiter: self.clone(),
+            slots: Vec::with_capacity(3),
+        };
+        if self.limits.max_total.is_some_and(|max| previous >= max) {
+            return Err(self.reject(ConnectionRejection::Total));
+        }
+
+        let ip = ip.to_canonical();
+        guard.take(
+            Slot::Ip(ip),
+            self.limits.max_per_ip,
+            ConnectionRejection::PerIp,
+        )?;
+        let prefix = self.prefix_of(ip);
+        guard.take(
+            Slot::Prefix(prefix),
+            self.limits.max_per_prefix,
+            ConnectionRejection::PerPrefix,
+        )?;
+        Ok(guard)
+    }
+
+    fn reject(&self, rejection: ConnectionRejection) -> ConnectionRejection {
+        self.rejected[rejection as usize].fetch_add(1, Ordering::Relaxed);
+        rejection
+    }
+
+    fn prefix_of(&self, ip: IpAddr) -> IpAddr {
+        match ip {
+            IpAddr::V4(v4) => {
+                let len = u32::from(self.limits.ipv4_prefix_len.min(32));
+                let mask = u32::MAX.checked_shl(32 - len).unwrap_or(0);
+                IpAddr::V4(Ipv4Addr::from(u32::from(v4) & mask))
+            }
+            IpAddr::V6(v6) => {
+                let len = u32::from(self.limits.ipv6_prefix_len.min(128));
+                let mask = u128::MAX.checked_shl(128 - len).unwrap_or(0);
+                IpAddr::V6(Ipv6Addr::from(u128::from(v6) & mask))
+            }
+        }
+    }
+}
+
+/// Holds one connection's place in the `ConnectionLimiter` until dropped
+pub(crate) struct ConnectionGuard {
+    limiter: Arc<ConnectionLimiter>,
+    slots: Vec<Slot>,
+}
+
+impl ConnectionGuard {
+    /// Count the connection against `peer` once it has authenticated
+    pub fn add_peer(&mut self, peer: PublicKey) -> Result<(), ConnectionRejection> {
+        let max = self.limiter.limits.max_per_peer;
+        self.take(Slot::Peer(peer), max, ConnectionRejection::PerPeer)
+    }
+
+    fn take(
+        &mut self,
+        slot: Slot,
+        max: Option<usize>,
+        rejection: ConnectionRejection,
+    ) -> Result<(), ConnectionRejection> {
+        let mut count = self.limiter.counts.entry(slot).or_insert(0);
+        if max.is_some_and(|max| *count >= max) {
+            drop(count);
+            return Err(self.limiter.reject(rejection));
+        }
+        *count += 1;
+        drop(count);
+        self.slots.push(slot);
+        Ok(())
+    }
+}
+
+impl Drop for ConnectionGuard {
+    fn drop(&mut self) {
+        for slot in &self.slots {
+            self.limiter.counts.remove_if_mut(slot, |_, count| {
+                *count -= 1;
+                *count == 0
+            });
+        }
+        self.limiter.active.fetch_sub(1, Ordering::AcqRel);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use mosaic_core::SecretKey;
+
+    fn ip(s: &str) -> IpAddr {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn per_ip_and_prefix_limits_are_released_on_drop() {
+        let limiter = Arc::new(ConnectionLimiter::new(ConnectionLimits {
+            max_total: Some(10),
+            max_per_ip: Some(2),
+            max_per_prefix: Some(3),
+            ..Default::default()
+        }));
+
+        let first = limiter.try_acquire(ip("192.0.2.1")).unwrap();
+        let _second = limiter.try_acquire(ip("192.0.2.1")).unwrap();
+        assert_eq!(
+            limiter.try_acquire(ip("192.0.2.1")).err(),
+            Some(ConnectionRejection::PerIp)
+        );
+        let _third = limiter.try_acquire(ip("192.0.2.2")).unwrap();
+        assert_eq!(
+            limiter.try_acquire(ip("192.0.2.3")).err(),
+            Some(ConnectionRejection::PerPrefix)
+        );
+        let _elsewhere = limiter.try_acquire(ip("198.51.100.1")).unwrap();
+
+        drop(first);
+        let _again = limiter.try_acquire(ip("192.0.2.1")).unwrap();
+
+        let stats = limiter.stats();
+        assert_eq!(stats.active, 4);
+        assert_eq!(stats.rejected_per_ip, 1);
+        assert_eq!(stats.rejected_per_prefix, 1);
+    }
+
+    #[test]
+    fn total_and_peer_limits() {
+        let limiter = Arc::new(ConnectionLimiter::new(ConnectionLimits {
+            max_total: Some(2),
+            max_per_peer: Some(1),
+            ..Default::default()
+        }));
+        let key = SecretKey::generate().public();
+
+        let mut first = limiter.try_acquire(ip("2001:db8::1")).unwrap();
+        first.add_peer(key).unwrap();
+        let mut second = limiter.try_acquire(ip("2001:db8:1::1")).unwrap();
+        assert_eq!(second.add_peer(key), Err(ConnectionRejection::PerPeer));
+        assert_eq!(
+            limiter.try_acquire(ip("203.0.113.9")).err(),
+            Some(ConnectionRejection::Total)
+        );
+
+        drop(second);
+        drop(first);
+        assert_eq!(limiter.stats().active, 0);
+        assert!(limiter.counts.is_empty());
+    }
+}
@@ -0,0 +1,1126 @@
+use std::ops::Bound;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Mutex, PoisonError, RwLock};
+
+use heed::types::Bytes;
+use mosaic_core::{Id, Kind, OwnedRecord, PublicKey, Record, Reference};
+use mosaic_store_lmdb::{InnerError as LmdbInnerError, Store as RawLmdbStore};
+
+use crate::{
+    Error, FsckProblem, InnerError, PutResult, RecordTotals, ScanEntry, ScanOrder, Store,
+    StoreError, StoreErrorKind, StoreStats,
+};
+
+const BYTES_PER_GB: usize = 1 << 30;
+
+/// Name of the LMDB data file within an environment directory
+const DATA_FILE: &str = "data.mdb";
+
+/// Extra LMDB table holding persisted `StoreStats` counters
+const STATS_TABLE: &str = "mosaic_server_stats";
+
+/// Extra LMDB table mapping insertion sequence numbers to record ids
+const SEQUENCE_TABLE: &str = "mosaic_server_sequence";
+
+/// Extra LMDB table mapping record ids to their sequence number and a live flag.
+/// Entries outlive deleted records so scan cursors stay valid.
+const SEQUENCE_BY_ID_TABLE: &str = "mosaic_server_sequence_by_id";
+
+/// Extra LMDB table holding counters that must never go backwards
+const COUNTERS_TABLE: &str = "mosaic_server_counters";
+
+/// The next insertion sequence number to hand out. Numbers are never reused,
+/// even after the newest record is deleted, so tombstoned cursors stay ahead
+/// of every later insert.
+const NEXT_SEQUENCE_KEY: &[u8] = b"next_sequence";
+
+const STATS_TOTAL_KEY: &[u8] = b"t";
+const STATS_KIND_PREFIX: u8 = b'k';
+const STATS_AUTHOR_PREFIX: u8 = b'a';
+
+/// LMDB-backed store adapter using `mosaic-store-lmdb`.
+///
+/// When opened with `open_growable` the LMDB map is grown (doubling, up to a
+/// ceiling) whenever a write finds it full, and the write is retried.
+///
+/// Statistics and the scan indexes are kept in extra tables that are updated
+/// alongside every insert and delete. Statistics are written in the same
+/// transaction as the record itself, and mirrored in memory so `stats()` never
+/// scans the records. A store written before statistics were kept has them
+/// counted from its records when it is first opened, and its records are
+/// numbered for insertion-order scans in the order the backend lists them.
+pub struct LmdbStore {
+    dir: PathBuf,
+    raw: RawLmdbStore,
+    max_map_size: usize,
+    // Current map size in bytes. Every use of `raw` holds this for reading, so
+    // holding it for writing guarantees none of those transactions is open.
+    map_size: RwLock<usize>,
+    // Backups copying the environment. A backup's copy runs without holding
+    // `map_size`, but it is counted here before that read lock is released,
+    // so a resize, which holds the write lock, sees every backup that started.
+    backups_running: AtomicUsize,
+    stats: Mutex<StoreStats>,
+}
+
+/// Disk usage of an `LmdbStore` compared to its capacity
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StoreUsage {
+    /// Bytes currently occupied by the store's files on disk
+    pub used_bytes: u64,
+
+    /// Current LMDB map size in bytes
+    pub map_size_bytes: u64,
+
+    /// Largest map size the store will grow to, in bytes
+    pub ceiling_bytes: u64,
+}
+
+impl StoreUsage {
+    /// Fraction (0.0 to 1.0) of the ceiling currently in use
+    #[must_use]
+    pub fn fraction_of_ceiling(&self) -> f64 {
+        if self.ceiling_bytes == 0 {
+            return 1.0;
+        }
+        self.used_bytes as f64 / self.ceiling_bytes as f64
+    }
+}
+
+impl LmdbStore {
+    /// Open or create a LMDB-backed store at `dir` with a fixed map size.
+    pub fn open<P: AsRef<Path>>(dir: P, max_size_gb: usize) -> Result<Self, Error> {
+        Self::open_growable(dir, max_size_gb, max_size_gb)
+    }
+
+    /// Open or create a LMDB-backed store at `dir` with a map of `initial_size_gb`
+    /// that grows automatically up to `max_size_gb` when it fills up.
+    pub fn open_growable<P: AsRef<Path>>(
+        dir: P,
+        initial_size_gb: usize,
+        max_size_gb: usize,
+    ) -> Result<Self, Error> {
+        let dir = dir.as_ref().to_path_buf();
+        let map_size_gb = initial_size_gb.max(1);
+        let raw = open_raw(&dir, map_size_gb).map_err(convert_store_error)?;
+        let stats = match load_stats(&raw)? {
+            Some(stats) => stats,
+            None => rebuild_stats(&raw).map_err(convert_store_error)?,
+        };
+        let store = Self {
+            dir,
+            raw,
+            max_map_size: max_size_gb.max(map_size_gb).saturating_mul(BYTES_PER_GB),
+            map_size: RwLock::new(map_size_gb.saturating_mul(BYTES_PER_GB)),
+            backups_running: AtomicUsize::new(0),
+            stats: Mutex::new(stats),
+        };
+        store.write(backfill_sequence)?;
+        Ok(store)
+    }
+
+    /// Report disk usage against the current map size and the growth ceiling.
+    pub fn usage(&self) -> Result<StoreUsage, Error> {
+        let map_size = *self.map_size.read().unwrap_or_else(PoisonError::into_inner);
+
+        let mut used_bytes = 0;
+        let entries =
+            std::fs::read_dir(&self.dir).map_err(|e| StoreError::new(StoreErrorKind::Io, e))?;
+        for entry in entries {
+            let metadata = entry
+                .and_then(|e| e.metadata())
+                .map_err(|e| StoreError::new(StoreErrorKind::Io, e))?;
+            if metadata.is_file() {
+                used_bytes += metadata.len();
+            }
+        }
+
+        Ok(StoreUsage {
+            used_bytes,
+            map_size_bytes: map_size as u64,
+            ceiling_bytes: self.max_map_size as u64,
+        })
+    }
+
+    /// Run a read-only operation against the raw store.
+    fn read<T, F>(&self, op: F) -> Result<T, Error>
+    where
+        F: FnOnce(&RawLmdbStore) -> Result<T, mosaic_store_lmdb::Error>,
+    {
+        let _map_size = self.map_size.read().unwrap_or_else(PoisonError::into_inner);
+        op(&self.raw).map_err(convert_store_error)
+    }
+
+    /// Run a write operation against the raw store, growing the map and
+    /// retrying whenever the operation finds it full.
+    fn write<T, F>(&self, op: F) -> Result<T, Error>
+    where
+        F: Fn(&RawLmdbStore) -> Result<T, mosaic_store_lmdb::Error>,
+    {
+        loop {
+            let observed = {
+                let map_size = self.map_size.read().unwrap_or_else(PoisonError::into_inner);
+                match op(&self.raw) {
+                    Ok(value) => return Ok(value),
+                    Err(e) if is_map_full(&e.inner) => *map_size,
+                    Err(e) => return Err(convert_store_error(e)),
+                }
+            };
+            self.grow(observed)?;
+        }
+    }
+
+    /// Enlarge the map. `observed` is the map size in bytes the caller saw
+    /// fill up; if another writer already grew it, do nothing.
+    fn grow(&self, observed: usize) -> Result<(), Error> {
+        let mut map_size = self
+            .map_size
+            .write()
+            .unwrap_or_else(PoisonError::into_inner);
+        if *map_size != observed {
+            return Ok(());
+        }
+
+        // A backup's copy runs in a transaction `map_size` does not cover
+        if self.backups_running.load(Ordering::SeqCst) > 0 {
+            return Err(StoreError::new(
+                StoreErrorKind::Transient,
+                "lmdb map is full and cannot grow while a backup is running",
+            )
+            .into());
+        }
+        if *map_size >= self.max_map_size {
+            return Err(StoreError::new(
+                StoreErrorKind::OutOfSpace,
+                format!("lmdb map is full at its {} byte ceiling", self.max_map_size),
+            )
+            .into());
+        }
+        let new_size = map_size.saturating_mul(2).min(self.max_map_size);
+
+        // SAFETY: no transaction is open. Every use of `raw` holds `map_size`
+        // for reading, and it is held here for writing. The backups, whose
+        // copies run outside that lock, were counted in `backups_running`
+        // under a read lock, so the count seen above is zero only if none is
+        // running, and none can start until the write lock is released.
+        unsafe { self.raw.env().resize(new_size) }
+            .map_err(|e| StoreError::new(classify_heed_error(&e), e))?;
+        *map_size = new_size;
+        Ok(())
+    }
+}
+
+impl Store for LmdbStore {
+    fn put_record(&self, record: &Record) -> Result<PutResult, Error> {
+        let result = self.write(|raw| {
+            let mut txn = raw.write_txn()?;
+            match raw.store_record_with_txn(&mut txn, record) {
+                Ok(_) => {}
+                Err(e) if matches!(e.inner, LmdbInnerError::Duplicate) => {
+                    return Ok(PutResult::Duplicate);
+                }
+                Err(e) => return Err(e),
+            }
+            update_persisted_stats(raw, &mut txn, record, RecordTotals::add)?;
+            index_inserted(raw, &mut txn, &record.id())?;
+            txn.commit()?;
+            Ok(PutResult::Inserted)
+        })?;
+
+        if result == PutResult::Inserted {
+            self.stats
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner)
+                .record_inserted(record);
+        }
+
+        Ok(result)
+    }
+
+    fn has_record(&self, reference: &Reference) -> Result<bool, Error> {
+        self.read(|raw| raw.has_record(*reference))
+    }
+
+    fn get_record(&self, reference: &Reference) -> Result<Option<OwnedRecord>, Error> {
+        let bytes = self.read(|raw| {
+            raw.get_record_by_ref(*reference)
+                .map(|found| found.map(|record| record.as_bytes().to_vec()))
+        })?;
+        match bytes {
+            Some(bytes) => {
+                let owned = OwnedRecord::from_vec(bytes)
+                    .map_err(|e| StoreError::new(StoreErrorKind::Corruption, e))?;
+                Ok(Some(owned))
+            }
+            None => Ok(None),
+        }
+    }
+
+    fn stats(&self) -> Result<StoreStats, Error> {
+        Ok(self
+            .stats
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clone())
+    }
+
+    fn author_stats(&self, author: &PublicKey) -> Result<RecordTotals, Error> {
+        Ok(self
+            .stats
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .by_author
+            .get(author)
+            .copied()
+            .unwrap_or_default())
+    }
+
+    /// Records are deleted by id without being verified, so one too damaged to
+    /// read back can still be removed. Its share of the statistics is only
+    /// subtracted if its bytes still parse.
+    fn delete_record(&self, reference: &Reference) -> Result<bool, Error> {
+        let id = match Id::from_bytes(reference.as_bytes()) {
+            Ok(id) => id,
+            Err(_) => match self.get_record(reference)? {
+                Some(record) => record.id(),
+                None => return Ok(false),
+            },
+        };
+
+        // The lookup runs inside the write transaction, so of two concurrent
+        // deletes of one record only the first finds it and updates the stats.
+        let removed = self.write(|raw| {
+            let mut txn = raw.write_txn()?;
+            if !is_indexed_live(raw, &txn, &id)? {
+                return Ok(None);
+            }
+            let bytes = raw
+                .get_record_by_id_with_txn(&txn, id)
+                .ok()
+                .flatten()
+                .map(|record| record.as_bytes().to_vec());
+
+            raw.delete_record_by_id_with_txn(&mut txn, id)?;
+            if let Some(record) = parse_record(bytes.as_deref()) {
+                update_persisted_stats(raw, &mut txn, record, RecordTotals::remove)?;
+            }
+            index_removed(raw, &mut txn, &id)?;
+            txn.commit()?;
+            Ok(Some(bytes))
+        })?;
+        let Some(bytes) = removed else {
+            return Ok(false);
+        };
+
+        if let Some(record) = parse_record(bytes.as_deref()) {
+            self.stats
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner)
+                .record_removed(record);
+        }
+        Ok(true)
+    }
+
+    fn scan(
+        &self,
+        order: ScanOrder,
+        after: Option<&Id>,
+        limit: usize,
+    ) -> Result<Vec<ScanEntry>, Error> {
+        self.read(|raw| {
+            let ids = match order {
+                ScanOrder::Insertion => scan_insertion_order(raw, after, limit)?,
+                ScanOrder::Timestamp => scan_timestamp_order(raw, after, limit)?,
+            };
+
+            let mut entries = Vec::with_capacity(ids.len());
+            for id in ids {
+                if let Some(record) = raw.get_record_by_ref(id.to_reference())? {
+                    entries.push(ScanEntry {
+                        id,
+                        bytes: record.as_bytes().to_vec(),
+                    });
+                }
+            }
+            Ok(entries)
+        })
+    }
+
+    /// Copy the environment, compacted, to `data.mdb` in `path`.
+    ///
+    /// The copy runs in its own read transaction without holding the store's
+    /// lock, so readers and writers carry on while it proceeds. A write that
+    /// finds the map full while a backup runs cannot grow it, and fails with a
+    /// transient error instead.
+    fn backup_to(&self, path: &Path) -> Result<(), Error> {
+        let io_error = |e| StoreError::new(StoreErrorKind::Io, e);
+        std::fs::create_dir_all(path).map_err(io_error)?;
+        let target = path.join(DATA_FILE);
+        if target.exists() {
+            return Err(io_error(std::io::Error::new(
+                std::io::ErrorKind::AlreadyExists,
+                format!("{} already exists", target.display()),
+            ))
+            .into());
+        }
+
+        let (env, _running) = {
+            let _map_size = self.map_size.read().unwrap_or_else(PoisonError::into_inner);
+            (
+                self.raw.env().clone(),
+                BackupRunning::start(&self.backups_running),
+            )
+        };
+        env.copy_to_path(&target, heed::CompactionOption::Enabled)
+            .map_err(|e| StoreError::new(classify_heed_error(&e), e))?;
+        Ok(())
+    }
+
+    /// Checks the tables this adapter keeps beside the records: every stored
+    /// record has a live insertion number that maps back to it, every number
+    /// names a stored record that claims it, and no number is at or past the
+    /// next one to be handed out. The author, kind, timestamp and tag indexes
+    /// inside `mosaic-store-lmdb` are private to it and are not checked.
+    fn check_indexes(&self) -> Result<Option<Vec<FsckProblem>>, Error> {
+        self.read(check_sequence_indexes).map(Some)
+    }
+}
+
+/// Counts a backup as running until dropped
+struct BackupRunning<'a>(&'a AtomicUsize);
+
+impl<'a> BackupRunning<'a> {
+    fn start(count: &'a AtomicUsize) -> BackupRunning<'a> {
+        count.fetch_add(1, Ordering::SeqCst);
+        BackupRunning(count)
+    }
+}
+
+impl Drop for BackupRunning<'_> {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+fn open_raw(dir: &Path, map_size_gb: usize) -> Result<RawLmdbStore, mosaic_store_lmdb::Error> {
+    RawLmdbStore::new(
+        dir,
+        vec![
+            STATS_TABLE,
+            SEQUENCE_TABLE,
+            SEQUENCE_BY_ID_TABLE,
+            COUNTERS_TABLE,
+        ],
+        map_size_gb,
+    )
+}
+
+fn extra_table(
+    raw: &RawLmdbStore,
+    name: &'static str,
+) -> Result<heed::Database<Bytes, Bytes>, mosaic_store_lmdb::Error> {
+    raw.get_extra_table(name)
+        .ok_or_else(|| LmdbInnerError::General(format!("missing lmdb table {name}")).into())
+}
+
+/// Read the persisted statistics counters into memory. Returns `None` if none
+/// have been persisted yet.
+fn load_stats(raw: &RawLmdbStore) -> Result<Option<StoreStats>, Error> {
+    let load = || -> Result<Option<StoreStats>, mosaic_store_lmdb::Error> {
+        let table = extra_table(raw, STATS_TABLE)?;
+        let txn = raw.read_txn()?;
+        if table.get(&txn, STATS_TOTAL_KEY)?.is_none() {
+            return Ok(None);
+        }
+        let mut stats = StoreStats::default();
+        for entry in table.iter(&txn)? {
+            let (key, value) = entry?;
+            let Some(totals) = decode_totals(value) else {
+                continue;
+            };
+            match key.split_first() {
+                Some((&STATS_KIND_PREFIX, kind)) => {
+                    if let Ok(kind) = <[u8; 8]>::try_from(kind) {
+                        stats
+                            .by_kind
+                            .insert(Kind::from_u64(u64::from_be_bytes(kind)), totals);
+                    }
+                }
+                Some((&STATS_AUTHOR_PREFIX, author)) => {
+                    if let Some(author) = <&[u8; 32]>::try_from(author)
+                        .ok()
+                        .and_then(|bytes| PublicKey::from_bytes(bytes).ok())
+                    {
+                        stats.by_author.insert(author, totals);
+                    }
+                }
+                _ if key == STATS_TOTAL_KEY => stats.total = totals,
+                _ => {}
+            }
+        }
+        Ok(Some(stats))
+    };
+    load().map_err(convert_store_error)
+}
+
+/// Count the statistics from the records themselves and persist them.
+fn rebuild_stats(raw: &RawLmdbStore) -> Result<StoreStats, mosaic_store_lmdb::Error> {
+    let table = extra_table(raw, STATS_TABLE)?;
+    let mut stats = StoreStats::default();
+    {
+        let txn = raw.read_txn()?;
+        for record in raw.iter_records(&txn)? {
+            stats.record_inserted(record?);
+        }
+    }
+
+    let mut txn = raw.write_txn()?;
+    table.clear(&mut txn)?;
+    table.put(&mut txn, STATS_TOTAL_KEY, &encode_totals(stats.total))?;
+    for (kind, totals) in &stats.by_kind {
+        table.put(&mut txn, &kind_stats_key(*kind), &encode_totals(*totals))?;
+    }
+    for (author, totals) in &stats.by_author {
+        table.put(&mut txn, &author_stats_key(author), &encode_totals(*totals))?;
+    }
+    txn.commit()?;
+    Ok(stats)
+}
+
+/// Update the extra tables for a newly inserted record.
+fn index_inserted(
+    raw: &RawLmdbStore,
+    txn: &mut heed::RwTxn<'_>,
+    id: &Id,
+) -> Result<(), mosaic_store_lmdb::Error> {
+    let sequence = extra_table(raw, SEQUENCE_TABLE)?;
+    let sequence_by_id = extra_table(raw, SEQUENCE_BY_ID_TABLE)?;
+
+    let next = next_sequence(raw, txn)?;
+    sequence.put(txn, &next.to_be_bytes(), id.as_bytes())?;
+    sequence_by_id.put(txn, id.as_bytes(), &encode_sequence(next, true))?;
+    Ok(())
+}
+
+/// Take the next insertion sequence number.
+fn next_sequence(
+    raw: &RawLmdbStore,
+    txn: &mut heed::RwTxn<'_>,
+) -> Result<u64, mosaic_store_lmdb::Error> {
+    let counters = extra_table(raw, COUNTERS_TABLE)?;
+    let next = match counters.get(txn, NEXT_SEQUENCE_KEY)?.and_then(decode_u64) {
+        Some(next) => next,
+        None => {
+            // A store written before the counter was kept: start past every
+            // number handed out so far, tombstones included
+            let sequence_by_id = extra_table(raw, SEQUENCE_BY_ID_TABLE)?;
+            let mut next = 0;
+            for entry in sequence_by_id.iter(txn)? {
+                let (_, value) = entry?;
+                if let Some((seq, _)) = decode_sequence(value) {
+                    next = next.max(seq + 1);
+                }
+            }
+            next
+        }
+    };
+    counters.put(txn, NEXT_SEQUENCE_KEY, &(next + 1).to_be_bytes())?;
+    Ok(next)
+}
+
+/// Whether the scan index holds `id` as a live record. It is updated in the
+/// same transactions as the records, so this is whether the record is stored.
+fn is_indexed_live(
+    raw: &RawLmdbStore,
+    txn: &heed::RwTxn<'_>,
+    id: &Id,
+) -> Result<bool, mosaic_store_lmdb::Error> {
+    let sequence_by_id = extra_table(raw, SEQUENCE_BY_ID_TABLE)?;
+    Ok(sequence_by_id
+        .get(txn, id.as_bytes())?
+        .and_then(decode_sequence)
+        .is_some_and(|(_, live)| live))
+}
+
+/// Compare the insertion sequence tables with the stored records.
+fn check_sequence_indexes(
+    raw: &RawLmdbStore,
+) -> Result<Vec<FsckProblem>, mosaic_store_lmdb::Error> {
+    let sequence = extra_table(raw, SEQUENCE_TABLE)?;
+    let sequence_by_id = extra_table(raw, SEQUENCE_BY_ID_TABLE)?;
+    let counters = extra_table(raw, COUNTERS_TABLE)?;
+    let txn = raw.read_txn()?;
+
+    let mut problems = Vec::new();
+    let mut problem = |id: Id, reason: String| {
+        problems.push(FsckProblem::IndexMismatch {
+            index: "insertion sequence",
+            id,
+            reason,
+        });
+    };
+
+    // Records too damaged to list are reported by `check_store` itself
+    for record in raw.iter_records(&txn)?.flatten() {
+        let id = record.id();
+        match sequence_by_id
+            .get(&txn, id.as_bytes())?
+            .and_then(decode_sequence)
+        {
+            Some((seq, true)) => {
+                if sequence.get(&txn, &seq.to_be_bytes())? != Some(id.as_bytes().as_slice()) {
+                    problem(id, format!("its number {seq} does not map back to it"));
+                }
+            }
+            Some((_, false)) => problem(id, "it is stored but marked deleted".to_owned()),
+            None => problem(id, "it is stored but has no number".to_owned()),
+        }
+    }
+
+    let next = counters.get(&txn, NEXT_SEQUENCE_KEY)?.and_then(decode_u64);
+    for entry in sequence.iter(&txn)? {
+        let (key, value) = entry?;
+        let (Some(seq), Some(id)) = (decode_u64(key), decode_id(value)) else {
+            continue;
+        };
+        // Damaged records still count as stored here
+        let stored = !matches!(raw.get_record_by_id_with_txn(&txn, id), Ok(None));
+        if !stored {
+            problem(id, format!("number {seq} names it but it is not stored"));
+        } else if sequence_by_id
+            .get(&txn, id.as_bytes())?
+            .and_then(decode_sequence)
+            != Some((seq, true))
+        {
+            problem(id, format!("number {seq} names it but it claims another"));
+        }
+        if next.is_some_and(|next| seq >= next) {
+            problem(id, format!("number {seq} would be handed out again"));
+        }
+    }
+
+    Ok(problems)
+}
+
+/// Update the extra tables for a deleted record, leaving a tombstone in the
+/// id-to-sequence table so cursors pointing at it keep working.
+fn index_removed(
+    raw: &RawLmdbStore,
+    txn: &mut heed::RwTxn<'_>,
+    id: &Id,
+) -> Result<(), mosaic_store_lmdb::Error> {
+    let sequence = extra_table(raw, SEQUENCE_TABLE)?;
+    let sequence_by_id = extra_table(raw, SEQUENCE_BY_ID_TABLE)?;
+
+    if let Some((seq, _)) = sequence_by_id
+        .get(txn, id.as_bytes())?
+        .and_then(decode_sequence)
+    {
+        sequence.delete(txn, &seq.to_be_bytes())?;
+        sequence_by_id.put(txn, id.as_bytes(), &encode_sequence(seq, false))?;
+    }
+    Ok(())
+}
+
+/// Number the records of a store written before the scan indexes existed, in
+/// the order the backend lists them. Does nothing once any record has a number.
+fn backfill_sequence(raw: &RawLmdbStore) -> Result<(), mosaic_store_lmdb::Error> {
+    let sequence_by_id = extra_table(raw, SEQUENCE_BY_ID_TABLE)?;
+    let ids = {
+        let txn = raw.read_txn()?;
+        if !sequence_by_id.is_empty(&txn)? {
+            return Ok(());
+        }
+        raw.iter_records(&txn)?
+            .map(|record| record.map(|record| record.id()))
+            .collect::<Result<Vec<Id>, _>>()?
+    };
+    if ids.is_empty() {
+        return Ok(());
+    }
+
+    let mut txn = raw.write_txn()?;
+    for id in &ids {
+        index_inserted(raw, &mut txn, id)?;
+    }
+    txn.commit()?;
+    Ok(())
+}
+
+/// Apply `change` to the persisted totals a record contributes to.
+fn update_persisted_stats(
+    raw: &RawLmdbStore,
+    txn: &mut heed::RwTxn<'_>,
+    record: &Record,
+    change: fn(&mut RecordTotals, u64),
+) -> Result<(), mosaic_store_lmdb::Error> {
+    let table = extra_table(raw, STATS_TABLE)?;
+    let len = record.as_bytes().len() as u64;
+
+    let kind_key = kind_stats_key(record.kind());
+    let author_key = author_stats_key(&record.author_public_key());
+
+    for key in [STATS_TOTAL_KEY, kind_key.as_slice(), author_key.as_slice()] {
+        let mut totals = table
+            .get(txn, key)?
+            .and_then(decode_totals)
+            .unwrap_or_default();
+        change(&mut totals, len);
+        if totals.records == 0 {
+            table.delete(txn, key)?;
+        } else {
+            table.put(txn, key, &encode_totals(totals))?;
+        }
+    }
+    Ok(())
+}
+
+fn parse_record(bytes: Option<&[u8]>) -> Option<&Record> {
+    bytes.and_then(|bytes| Record::from_bytes(bytes).ok())
+}
+
+fn kind_stats_key(kind: Kind) -> [u8; 9] {
+    let mut key = [STATS_KIND_PREFIX; 9];
+    key[1..].copy_from_slice(&kind.to_u64().to_be_bytes());
+    key
+}
+
+fn author_stats_key(author: &PublicKey) -> [u8; 33] {
+    let mut key = [STATS_AUTHOR_PREFIX; 33];
+    key[1..].copy_from_slice(author.as_bytes());
+    key
+}
+
+fn scan_insertion_order(
+    raw: &RawLmdbStore,
+    after: Option<&Id>,
+    limit: usize,
+) -> Result<Vec<Id>, mosaic_store_lmdb::Error> {
+    let sequence = extra_table(raw, SEQUENCE_TABLE)?;
+    let sequence_by_id = extra_table(raw, SEQUENCE_BY_ID_TABLE)?;
+    let txn = raw.read_txn()?;
+
+    let start = match after {
+        Some(id) => match sequence_by_id
+            .get(&txn, id.as_bytes())?
+            .and_then(decode_sequence)
+        {
+            Some((seq, _)) => Some(seq.to_be_bytes()),
+            None => {
+                return Err(LmdbInnerError::General(
+                    "scan cursor is not a known record id".to_owned(),
+                )
+                .into());
+            }
+        },
+        None => None,
+    };
+    let lower = match &start {
+        Some(seq) => Bound::Excluded(seq.as_slice()),
+        None => Bound::Unbounded,
+    };
+
+    let mut ids = Vec::new();
+    for entry in sequence
+        .range(&txn, &(lower, Bound::Unbounded))?
+        .take(limit)
+    {
+        let (_, id) = entry?;
+        if let Some(id) = decode_id(id) {
+            ids.push(id);
+        }
+    }
+    Ok(ids)
+}
+
+fn scan_timestamp_order(
+    raw: &RawLmdbStore,
+    after: Option<&Id>,
+    limit: usize,
+) -> Result<Vec<Id>, mosaic_store_lmdb::Error> {
+    let sequence_by_id = extra_table(raw, SEQUENCE_BY_ID_TABLE)?;
+    let txn = raw.read_txn()?;
+
+    let lower = match after {
+        Some(id) => Bound::Excluded(id.as_bytes().as_slice()),
+        None => Bound::Unbounded,
+    };
+
+    let mut ids = Vec::new();
+    for entry in sequence_by_id.range(&txn, &(lower, Bound::Unbounded))? {
+        if ids.len() >= limit {
+            break;
+        }
+        let (id, value) = entry?;
+        let live = decode_sequence(value).is_some_and(|(_, live)| live);
+        if let (true, Some(id)) = (live, decode_id(id)) {
+            ids.push(id);
+        }
+    }
+    Ok(ids)
+}
+
+fn encode_sequence(seq: u64, live: bool) -> [u8; 9] {
+    let mut bytes = [0; 9];
+    bytes[..8].copy_from_slice(&seq.to_be_bytes());
+    bytes[8] = u8::from(live);
+    bytes
+}
+
+fn decode_sequence(bytes: &[u8]) -> Option<(u64, bool)> {
+    let seq = decode_u64(bytes.get(..8)?)?;
+    Some((seq, bytes.get(8) == Some(&1)))
+}
+
+fn decode_u64(bytes: &[u8]) -> Option<u64> {
+    Some(u64::from_be_bytes(bytes.try_into().ok()?))
+}
+
+fn decode_id(bytes: &[u8]) -> Option<Id> {
+    let bytes: &[u8; 48] = bytes.try_into().ok()?;
+    Id::from_bytes(bytes).ok()
+}
+
+fn encode_totals(totals: RecordTotals) -> [u8; 16] {
+    let mut bytes = [0; 16];
+    bytes[..8].copy_from_slice(&totals.records.to_be_bytes());
+    bytes[8..].copy_from_slice(&totals.bytes.to_be_bytes());
+    bytes
+}
+
+fn decode_totals(bytes: &[u8]) -> Option<RecordTotals> {
+    let records = u64::from_be_bytes(bytes.get(..8)?.try_into().ok()?);
+    let bytes = u64::from_be_bytes(bytes.get(8..16)?.try_into().ok()?);
+    Some(RecordTotals { records, bytes })
+}
+
+fn is_map_full(inner: &LmdbInnerError) -> bool {
+    matches!(
+        inner,
+        LmdbInnerError::Lmdb(heed::Error::Mdb(heed::MdbError::MapFull))
+    )
+}
+
+fn convert_store_error(error: mosaic_store_lmdb::Error) -> Error {
+    let kind = classify_store_error(&error.inner);
+    InnerError::Store(StoreError::new(kind, error)).into_err()
+}
+
+fn classify_store_error(inner: &LmdbInnerError) -> StoreErrorKind {
+    match inner {
+        LmdbInnerError::Io(err) if err.kind() == std::io::ErrorKind::StorageFull => {
+            StoreErrorKind::OutOfSpace
+        }
+        LmdbInnerError::Io(_) => StoreErrorKind::Io,
+        LmdbInnerError::Lmdb(err) => classify_heed_error(err),
+        LmdbInnerError::EndOfInput | LmdbInnerError::MosaicCore(_) => StoreErrorKind::Corruption,
+        LmdbInnerError::FilterTooWide => StoreErrorKind::FilterTooWide,
+        LmdbInnerError::General(_) | LmdbInnerError::BufferTooSmall | LmdbInnerError::Duplicate => {
+            StoreErrorKind::Other
+        }
+    }
+}
+
+fn classify_heed_error(err: &heed::Error) -> StoreErrorKind {
+    use heed::MdbError;
+
+    match err {
+        heed::Error::Io(e) if e.kind() == std::io::ErrorKind::StorageFull => {
+            StoreErrorKind::OutOfSpace
+        }
+        heed::Error::Io(_) => StoreErrorKind::Io,
+        heed::Error::Mdb(MdbError::MapFull) => StoreErrorKind::OutOfSpace,
+        heed::Error::Mdb(
+            MdbError::Corrupted
+            | MdbError::PageNotFound
+            | MdbError::Panic
+            | MdbError::VersionMismatch
+            | MdbError::Invalid,
+        ) => StoreErrorKind::Corruption,
+        heed::Error::Mdb(
+            MdbError::ReadersFull
+            | MdbError::MapResized
+            | MdbError::TxnFull
+            | MdbError::CursorFull
+            | MdbError::PageFull,
+        ) => StoreErrorKind::Transient,
+        heed::Error::Decoding(_) => StoreErrorKind::Corruption,
+        _ => StoreErrorKind::Other,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use mosaic_core::{
+        EMPTY_TAG_SET, Kind, OwnedRecord, RecordAddressData, RecordParts, RecordSigningData,
+        SecretKey, Timestamp,
+    };
+
+    use crate::test_support::conformance;
+
+    fn build_record() -> OwnedRecord {
+        let signing_key = SecretKey::generate();
+        OwnedRecord::new(&RecordParts {
+            signing_data: RecordSigningData::SecretKey(signing_key.clone()),
+            address_data: RecordAddressData::Random(signing_key.public(), Kind::KEY_SCHEDULE),
+            timestamp: Timestamp::now().unwrap(),
+            flags: Default::default(),
+            tag_set: &EMPTY_TAG_SET,
+            payload: b"store test payload",
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn conforms_to_the_store_contract() {
+        let mut temp_dirs = Vec::new();
+        conformance::check_store(|| {
+            let temp_dir = tempfile::tempdir().unwrap();
+            let store = LmdbStore::open(temp_dir.path(), 1).unwrap();
+            temp_dirs.push(temp_dir);
+            store
+        });
+
+        let temp_dir = tempfile::tempdir().unwrap();
+        conformance::check_stats_persist(|| LmdbStore::open(temp_dir.path(), 1).unwrap());
+    }
+
+    #[test]
+    fn missing_stats_are_rebuilt_on_open() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let records: Vec<OwnedRecord> = (0..3).map(|_| build_record()).collect();
+        let expected = {
+            let store = LmdbStore::open(temp_dir.path(), 1).unwrap();
+            for record in &records {
+                store.put_record(record.as_ref()).unwrap();
+            }
+
+            // Forget the statistics, as in a store written before they were kept.
+            let table = extra_table(&store.raw, STATS_TABLE).unwrap();
+            let mut txn = store.raw.write_txn().unwrap();
+            table.clear(&mut txn).unwrap();
+            txn.commit().unwrap();
+            store.stats().unwrap()
+        };
+
+        let reopened = LmdbStore::open(temp_dir.path(), 1).unwrap();
+        assert_eq!(reopened.stats().unwrap(), expected);
+        assert_eq!(
+            reopened
+                .author_stats(&records[0].author_public_key())
+                .unwrap()
+                .records,
+            1
+        );
+    }
+
+    #[test]
+    fn unindexed_records_are_backfilled_on_open() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let records: Vec<OwnedRecord> = (0..3).map(|_| build_record()).collect();
+        {
+            let store = LmdbStore::open(temp_dir.path(), 1).unwrap();
+            for record in &records {
+                store.put_record(record.as_ref()).unwrap();
+            }
+
+            // Forget the scan indexes, as in a store written before they existed.
+            let mut txn = store.raw.write_txn().unwrap();
+            for name in [SEQUENCE_TABLE, SEQUENCE_BY_ID_TABLE, COUNTERS_TABLE] {
+                extra_table(&store.raw, name)
+                    .unwrap()
+                    .clear(&mut txn)
+                    .unwrap();
+            }
+            txn.commit().unwrap();
+            assert!(
+                store
+                    .scan(ScanOrder::Insertion, None, 10)
+                    .unwrap()
+                    .is_empty()
+            );
+        }
+
+        let reopened = LmdbStore::open(temp_dir.path(), 1).unwrap();
+        let mut scanned: Vec<Id> = reopened
+            .scan(ScanOrder::Insertion, None, 10)
+            .unwrap()
+            .iter()
+            .map(|entry| entry.id)
+            .collect();
+        let mut expected: Vec<Id> = records.iter().map(|r| r.id()).collect();
+        scanned.sort_unstable_by_key(|id| *id.as_bytes());
+        expected.sort_unstable_by_key(|id| *id.as_bytes());
+        assert_eq!(scanned, expected);
+        assert_eq!(
+            reopened.scan(ScanOrder::Timestamp, None, 10).unwrap().len(),
+            3
+        );
+
+        // New records are numbered after the backfilled ones.
+        let newest = build_record();
+        reopened.put_record(newest.as_ref()).unwrap();
+        let all = reopened.scan(ScanOrder::Insertion, None, 10).unwrap();
+        assert_eq!(all.last().unwrap().id, newest.id());
+    }
+
+    #[test]
+    fn damaged_sequence_index_is_reported_by_fsck() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let store = LmdbStore::open(temp_dir.path(), 1).unwrap();
+        let records: Vec<OwnedRecord> = (0..3).map(|_| build_record()).collect();
+        for record in &records {
+            store.put_record(record.as_ref()).unwrap();
+        }
+
+        let report = crate::check_store(&store, None).unwrap();
+        assert!(report.indexes_checked);
+        assert!(report.is_clean(), "{:?}", report.problems);
+
+        // Drop the second record's number behind the store's back.
+        let sequence = extra_table(&store.raw, SEQUENCE_TABLE).unwrap();
+        let mut txn = store.raw.write_txn().unwrap();
+        sequence.delete(&mut txn, &1_u64.to_be_bytes()).unwrap();
+        txn.commit().unwrap();
+
+        // Insertion scans no longer reach it, so the totals disagree as well.
+        let report = crate::check_store(&store, None).unwrap();
+        assert_eq!(report.records_checked, 2);
+        assert!(report.problems.contains(&FsckProblem::IndexMismatch {
+            index: "insertion sequence",
+            id: records[1].id(),
+            reason: "its number 1 does not map back to it".to_owned(),
+        }));
+    }
+
+    #[test]
+    fn backend_errors_are_classified() {
+        let error: mosaic_store_lmdb::Error = LmdbInnerError::FilterTooWide.into();
+        let converted = convert_store_error(error);
+        let InnerError::Store(store_error) = &converted.inner else {
+            panic!("expected a store error, got {converted}");
+        };
+        assert_eq!(store_error.kind(), StoreErrorKind::FilterTooWide);
+        assert!(std::error::Error::source(store_error).is_some());
+
+        let map_full = LmdbInnerError::Lmdb(heed::Error::Mdb(heed::MdbError::MapFull));
+        assert_eq!(classify_store_error(&map_full), StoreErrorKind::OutOfSpace);
+
+        let io = LmdbInnerError::Io(std::io::Error::other("boom"));
+        assert_eq!(classify_store_error(&io), StoreErrorKind::Io);
+    }
+
+    #[test]
+    fn backup_opens_as_a_store() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let store = LmdbStore::open(temp_dir.path(), 1).unwrap();
+        let record = build_record();
+        store.put_record(record.as_ref()).unwrap();
+
+        let backup_root = tempfile::tempdir().unwrap();
+        let backup_dir = backup_root.path().join("backup");
+        store.backup_to(&backup_dir).unwrap();
+        assert!(store.backup_to(&backup_dir).is_err());
+
+        // Writes after the snapshot are not in it.
+        store.put_record(build_record().as_ref()).unwrap();
+
+        let restored = LmdbStore::open(&backup_dir, 1).unwrap();
+        let reference = record.id().to_reference();
+        assert!(restored.has_record(&reference).unwrap());
+        assert_eq!(restored.stats().unwrap().total.records, 1);
+    }
+
+    #[test]
+    fn map_grows_up_to_ceiling() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let store = LmdbStore::open_growable(temp_dir.path(), 1, 2).unwrap();
+        let record = build_record();
+        store.put_record(record.as_ref()).unwrap();
+
+        let usage = store.usage().unwrap();
+        let gb = BYTES_PER_GB as u64;
+        assert_eq!(usage.map_size_bytes, gb);
+        assert_eq!(usage.ceiling_bytes, 2 * gb);
+        assert!(usage.used_bytes > 0);
+
+        store.grow(BYTES_PER_GB).unwrap();
+        assert_eq!(store.usage().unwrap().map_size_bytes, 2 * gb);
+
+        // A stale observation is a no-op rather than a second resize.
+        store.grow(BYTES_PER_GB).unwrap();
+        assert_eq!(store.usage().unwrap().map_size_bytes, 2 * gb);
+
+        let err = store.grow(2 * BYTES_PER_GB).unwrap_err();
+        let InnerError::Store(store_error) = &err.inner else {
+            panic!("expected a store error, got {err}");
+        };
+        assert_eq!(store_error.kind(), StoreErrorKind::OutOfSpace);
+
+        let reference = record.id().to_reference();
+        let fetched = store.get_record(&reference).unwrap().unwrap();
+        assert_eq!(fetched.as_bytes(), record.as_bytes());
+    }
+
+    #[test]
+    fn map_does_not_grow_during_a_backup() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let store = LmdbStore::open_growable(temp_dir.path(), 1, 2).unwrap();
+
+        let running = BackupRunning::start(&store.backups_running);
+        let err = store.grow(BYTES_PER_GB).unwrap_err();
+        let InnerError::Store(store_error) = &err.inner else {
+            panic!("expected a store error, got {err}");
+        };
+        assert_eq!(store_error.kind(), StoreErrorKind::Transient);
+
+        drop(running);
+        store.grow(BYTES_PER_GB).unwrap();
+        assert_eq!(
+            store.usage().unwrap().map_size_bytes,
+            2 * BYTES_PER_GB as u64
+        );
+    }
+
+    #[test]
+    fn full_map_grows_on_write() {
+        const MIB: usize = 1 << 20;
+
+        // Shrink a fresh store's map so a few hundred records fill it.
+        let temp_dir = tempfile::tempdir().unwrap();
+        let mut store = LmdbStore::open(temp_dir.path(), 1).unwrap();
+        unsafe { store.raw.env().resize(MIB) }.unwrap();
+        *store.map_size.get_mut().unwrap() = MIB;
+        store.max_map_size = 4 * MIB;
+
+        let payload = vec![0x5A; 16 * 1024];
+        let mut stored = Vec::new();
+        let err = loop {
+            let record = crate::test_support::build_record(&payload);
+            match store.put_record(record.as_ref()) {
+                Ok(result) => {
+                    assert_eq!(result, PutResult::Inserted);
+                    stored.push(record);
+                }
+                Err(e) => break e,
+            }
+            assert!(stored.len() < 10_000, "the map never filled up");
+        };
+
+        let InnerError::Store(store_error) = &err.inner else {
+            panic!("expected a store error, got {err}");
+        };
+        assert_eq!(store_error.kind(), StoreErrorKind::OutOfSpace);
+        assert_eq!(store.usage().unwrap().map_size_bytes, 4 * MIB as u64);
+        assert!(stored.len() * payload.len() > MIB);
+
+        for record in &stored {
+            let reference = record.id().to_reference();
+            assert!(store.has_record(&reference).unwrap());
+        }
+        assert_eq!(store.stats().unwrap().total.records, stored.len() as u64);
+    }
+}
@@ -0,0 +1,452 @@
+use std::collections::VecDeque;
+use std::fmt;
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Mutex, PoisonError};
+
+use mosaic_core::{Id, OwnedRecord, PublicKey, Record, Reference};
+
+use crate::{
+    Error, FsckProblem, PutResult, RecordTotals, ScanEntry, ScanOrder, Store, StoreStats, hex,
+};
+
+/// Most divergences held for `MirroredStore::take_divergences`; older ones are
+/// dropped but still counted.
+const MAX_HELD_DIVERGENCES: usize = 1024;
+
+/// Records read per scan by `MirroredStore::verify`
+const VERIFY_BATCH: usize = 256;
+
+/// A disagreement between the primary and a secondary of a `MirroredStore`.
+///
+/// `secondary` is the index of the secondary in the order they were added.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Divergence {
+    /// The primary accepted a write that the secondary failed to apply
+    WriteFailed {
+        secondary: usize,
+        id: Id,
+        reason: String,
+    },
+
+    /// The primary and the secondary disagree on whether a put was a duplicate
+    PutMismatch {
+        secondary: usize,
+        id: Id,
+        primary: PutResult,
+        mirrored: PutResult,
+    },
+
+    /// The primary and the secondary disagree on whether a deleted record existed
+    DeleteMismatch {
+        secondary: usize,
+        id: Id,
+        primary: bool,
+        mirrored: bool,
+    },
+
+    /// A read missed or failed on the primary and was answered by the secondary
+    ReadFallback {
+        secondary: usize,
+        id: Id,
+        primary_error: Option<String>,
+    },
+
+    /// `verify` found a record on the primary that the secondary lacks
+    MissingFromSecondary { secondary: usize, id: Id },
+
+    /// `verify` found a record on the secondary that the primary lacks
+    MissingFromPrimary { secondary: usize, id: Id },
+}
+
+impl fmt::Display for Divergence {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Divergence::WriteFailed {
+                secondary,
+                id,
+                reason,
+            } => write!(
+                f,
+                "secondary {secondary} failed to apply write of {}: {reason}",
+                hex::encode(id.as_bytes())
+            ),
+            Divergence::PutMismatch {
+                secondary,
+                id,
+                primary,
+                mirrored,
+            } => write!(
+                f,
+                "put of {} was {primary:?} on the primary but {mirrored:?} on secondary {secondary}",
+                hex::encode(id.as_bytes())
+            ),
+            Divergence::DeleteMismatch {
+                secondary,
+                id,
+                primary,
+                mirrored,
+            } => write!(
+                f,
+                "delete of {} found it present={primary} on the primary but present={mirrored} on secondary {secondary}",
+                hex::encode(id.as_bytes())
+            ),
+            Divergence::ReadFallback {
+                secondary,
+                id,
+                primary_error: None,
+            } => write!(
+                f,
+                "record {} was missing from the primary and read from secondary {secondary}",
+                hex::encode(id.as_bytes())
+            ),
+            Divergence::ReadFallback {
+                secondary,
+                id,
+                primary_error: Some(e),
+            } => write!(
+                f,
+                "record {} was read from secondary {secondary} after the primary failed: {e}",
+                hex::encode(id.as_bytes())
+            ),
+            Divergence::MissingFromSecondary { secondary, id } => {
+                write!(
+                    f,
+                    "record {} is missing from secondary {secondary}",
+                    hex::encode(id.as_bytes())
+                )
+            }
+            Divergence::MissingFromPrimary { secondary, id } => write!(
+                f,
+                "record {} is on secondary {secondary} but not on the primary",
+                hex::encode(id.as_bytes())
+            ),
+        }
+    }
+}
+
+/// A `Store` that writes every record to a primary and one or more secondary
+/// stores, for redundancy across disks or backends.
+///
+/// Writes succeed or fail with the primary; secondaries are written after it
+/// and their failures are recorded as `Divergence`s rather than returned.
+/// Reads are served by the primary, falling back to the secondaries in order
+/// when it misses or fails. Statistics come from the primary, or from the
+/// first secondary that answers if the primary fails. Scans only ever read the
+/// primary: a scan cursor is only meaningful to the store that produced it, so
+/// if the primary fails the scan fails rather than resuming elsewhere.
+pub struct MirroredStore {
+    primary: Box<dyn Store>,
+    secondaries: Vec<Box<dyn Store>>,
+    divergences: Mutex<VecDeque<Divergence>>,
+    divergence_count: AtomicU64,
+}
+
+impl MirroredStore {
+    /// Mirror writes to `primary` with no secondaries yet
+    pub fn new(primary: impl Store + 'static) -> MirroredStore {
+        MirroredStore {
+            primary: Box::new(primary),
+            secondaries: Vec::new(),
+            divergences: Mutex::new(VecDeque::new()),
+            divergence_count: AtomicU64::new(0),
+        }
+    }
+
+    /// Add a secondary store that receives a copy of every write
+    #[must_use]
+    pub fn with_secondary(mut self, secondary: impl Store + 'static) -> MirroredStore {
+        self.secondaries.push(Box::new(secondary));
+        self
+    }
+
+    /// The number of secondary stores
+    pub fn secondary_count(&self) -> usize {
+        self.secondaries.len()
+    }
+
+    /// Total divergences seen since the store was created
+    pub fn divergence_count(&self) -> u64 {
+        self.divergence_count.load(Ordering::Relaxed)
+    }
+
+    /// Remove and return the divergences seen since the last call, oldest
+    /// first. At most the latest 1024 are kept between calls.
+    pub fn take_divergences(&self) -> Vec<Divergence> {
+        self.lock().drain(..).collect()
+    }
+
+    /// Compare every secondary against the primary by scanning both ways,
+    /// returning (and also recording) the records held by only one side.
+    ///
+    /// This reads every record id on every store, so it is meant for
+    /// maintenance rather than the request path.
+    pub fn verify(&self) -> Result<Vec<Divergence>, Error> {
+        let mut found = Vec::new();
+        for (secondary, store) in self.secondaries.iter().enumerate() {
+            for id in missing_from(&*self.primary, &**store)? {
+                found.push(Divergence::MissingFromSecondary { secondary, id });
+            }
+            for id in missing_from(&**store, &*self.primary)? {
+                found.push(Divergence::MissingFromPrimary { secondary, id });
+            }
+        }
+        for divergence in &found {
+            self.report(divergence.clone());
+        }
+        Ok(found)
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, VecDeque<Divergence>> {
+        self.divergences
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn report(&self, divergence: Divergence) {
+        self.divergence_count.fetch_add(1, Ordering::Relaxed);
+        let mut held = self.lock();
+        if held.len() == MAX_HELD_DIVERGENCES {
+            held.pop_front();
+        }
+        held.push_back(divergence);
+    }
+
+    /// Run a read against the primary, then each secondary until one succeeds
+    fn read_any<T>(&self, read: impl Fn(&dyn Store) -> Result<T, Error>) -> Result<T, Error> {
+        let primary_error = match read(&*self.primary) {
+            Ok(value) => return Ok(value),
+            Err(e) => e,
+        };
+        for store in &self.secondaries {
+            if let Ok(value) = read(&**store) {
+                return Ok(value);
+            }
+        }
+        Err(primary_error)
+    }
+}
+
+/// Ids of records scanned from `source` that `target` does not have
+fn missing_from(source: &dyn Store, target: &dyn Store) -> Result<Vec<Id>, Error> {
+    let mut missing = Vec::new();
+    let mut after = None;
+    loop {
+        let batch = source.scan(ScanOrder::Insertion, after.as_ref(), VERIFY_BATCH)?;
+        let Some(last) = batch.last() else {
+            break;
+        };
+        after = Some(last.id);
+        for entry in &batch {
+            if !target.has_record(&entry.id.to_reference())? {
+                missing.push(entry.id);
+            }
+        }
+    }
+    Ok(missing)
+}
+
+impl Store for MirroredStore {
+    fn put_record(&self, record: &Record) -> Result<PutResult, Error> {
+        let result = self.primary.put_record(record)?;
+        let id = record.id();
+        for (secondary, store) in self.secondaries.iter().enumerate() {
+            match store.put_record(record) {
+                Ok(mirrored) if mirrored == result => {}
+                Ok(mirrored) => self.report(Divergence::PutMismatch {
+                    secondary,
+                    id,
+                    primary: result,
+                    mirrored,
+                }),
+                Err(e) => self.report(Divergence::WriteFailed {
+                    secondary,
+                    id,
+                    reason: e.to_string(),
+                }),
+            }
+        }
+        Ok(result)
+    }
+
+    fn has_record(&self, reference: &Reference) -> Result<bool, Error> {
+        // Only a record found on a secondary has an id to report divergence
+        // with, so that case goes through get_record.
+        let primary_error = match self.primary.has_record(reference) {
+            Ok(true) => return Ok(true),
+            Ok(false) => None,
+            Err(e) => Some(e),
+        };
+        if self.secondaries.is_empty() {
+            return primary_error.map_or(Ok(false), Err);
+        }
+        Ok(self.get_record(reference)?.is_some())
+    }
+
+    fn get_record(&self, reference: &Reference) -> Result<Option<OwnedRecord>, Error> {
+        let primary_error = match self.primary.get_record(reference) {
+            Ok(Some(record)) => return Ok(Some(record)),
+            Ok(None) => None,
+            Err(e) => Some(e),
+        };
+
+        for (secondary, store) in self.secondaries.iter().enumerate() {
+            if let Ok(Some(record)) = store.get_record(reference) {
+                self.report(Divergence::ReadFallback {
+                    secondary,
+                    id: record.id(),
+                    primary_error: primary_error.as_ref().map(ToString::to_string),
+                });
+                return Ok(Some(record));
+            }
+        }
+
+        primary_error.map_or(Ok(None), Err)
+    }
+
+    fn stats(&self) -> Result<StoreStats, Error> {
+        self.read_any(|store| store.stats())
+    }
+
+    fn author_stats(&self, author: &PublicKey) -> Result<RecordTotals, Error> {
+        self.read_any(|store| store.author_stats(author))
+    }
+
+    fn delete_record(&self, reference: &Reference) -> Result<bool, Error> {
+        // Resolve the id first: the reference may be an address, and a record
+        // that no store holds needs no delete.
+        let Some(id) = self.get_record(reference)?.map(|record| record.id()) else {
+            return Ok(false);
+        };
+        let deleted = self.primary.delete_record(reference)?;
+        for (secondary, store) in self.secondaries.iter().enumerate() {
+            match store.delete_record(reference) {
+                Ok(mirrored) if mirrored == deleted => {}
+                Ok(mirrored) => self.report(Divergence::DeleteMismatch {
+                    secondary,
+                    id,
+                    primary: deleted,
+                    mirrored,
+                }),
+                Err(e) => self.report(Divergence::WriteFailed {
+                    secondary,
+                    id,
+                    reason: e.to_string(),
+                }),
+            }
+        }
+        Ok(deleted)
+    }
+
+    fn scan(
+        &self,
+        order: ScanOrder,
+        after: Option<&Id>,
+        limit: usize,
+    ) -> Result<Vec<ScanEntry>, Error> {
+        self.primary.scan(order, after, limit)
+    }
+
+    fn backup_to(&self, path: &Path) -> Result<(), Error> {
+        self.primary.backup_to(path)
+    }
+
+    fn check_indexes(&self) -> Result<Option<Vec<FsckProblem>>, Error> {
+        self.primary.check_indexes()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::test_support::{InMemoryStore, build_record};
+    use crate::{Fault, FaultyStore, StoreErrorKind, StoreMethod};
+
+    #[test]
+    fn writes_reach_every_store_and_mismatches_are_reported() {
+        let stale = build_record(b"already on the secondary");
+        let secondary = InMemoryStore::default();
+        secondary.put_record(stale.as_ref()).unwrap();
+        let store = MirroredStore::new(InMemoryStore::default())
+            .with_secondary(secondary)
+            .with_secondary(InMemoryStore::default());
+
+        let record = build_record(b"mirrored");
+        assert_eq!(
+            store.put_record(record.as_ref()).unwrap(),
+            PutResult::Inserted
+        );
+        assert_eq!(
+            store.put_record(stale.as_ref()).unwrap(),
+            PutResult::Inserted
+        );
+        for secondary in &store.secondaries {
+            assert!(secondary.has_record(&record.id().to_reference()).unwrap());
+        }
+
+        assert_eq!(
+            store.take_divergences(),
+            vec![Divergence::PutMismatch {
+                secondary: 0,
+                id: stale.id(),
+                primary: PutResult::Inserted,
+                mirrored: PutResult::Duplicate,
+            }]
+        );
+        assert!(store.take_divergences().is_empty());
+        assert_eq!(store.divergence_count(), 1);
+    }
+
+    #[test]
+    fn reads_fall_back_to_secondaries_and_verify_finds_gaps() {
+        let only_secondary = build_record(b"lost from the primary");
+        let only_primary = build_record(b"never mirrored");
+        let primary = InMemoryStore::default();
+        primary.put_record(only_primary.as_ref()).unwrap();
+        let secondary = InMemoryStore::default();
+        secondary.put_record(only_secondary.as_ref()).unwrap();
+        let store = MirroredStore::new(primary).with_secondary(secondary);
+
+        let reference = only_secondary.id().to_reference();
+        let fetched = store.get_record(&reference).unwrap().unwrap();
+        assert_eq!(fetched.as_bytes(), only_secondary.as_bytes());
+        assert!(store.has_record(&reference).unwrap());
+        assert_eq!(
+            store.take_divergences()[0],
+            Divergence::ReadFallback {
+                secondary: 0,
+                id: only_secondary.id(),
+                primary_error: None,
+            }
+        );
+
+        let found = store.verify().unwrap();
+        assert_eq!(
+            found,
+            vec![
+                Divergence::MissingFromSecondary {
+                    secondary: 0,
+                    id: only_primary.id(),
+                },
+                Divergence::MissingFromPrimary {
+                    secondary: 0,
+                    id: only_secondary.id(),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn scans_stay_on_the_primary() {
+        let primary = FaultyStore::new(InMemoryStore::default(), 1)
+            .with_fault(StoreMethod::Scan, Fault::always(StoreErrorKind::Io));
+        let secondary = InMemoryStore::default();
+        secondary
+            .put_record(build_record(b"secondary only").as_ref())
+            .unwrap();
+        let store = MirroredStore::new(primary).with_secondary(secondary);
+
+        assert!(store.scan(ScanOrder::Insertion, None, 10).is_err());
+    }
+}
@@ -0,0 +1,131 @@
+use std::collections::HashSet;
+use std::fmt;
+use std::sync::Arc;
+
+use mosaic_core::{Id, OwnedRecord, PublicKey, Record, ResultCode};
+
+use crate::{Error, PutResult, ScanOrder, Store};
+
+/// Holds submissions from untrusted authors for review.
+///
+/// Held records go into a separate `pending` store rather than the main one,
+/// so they are not served until an operator releases them. Use a persistent
+/// store (such as a second `LmdbStore`) to keep the queue across restarts.
+///
+/// A `SubmissionResult` of `PENDING_CODE` tells the client its record is
+/// held; stored records are answered `Accepted` whether or not moderation is
+/// enabled. Held records count against their author's quota, so with quotas
+/// configured no author can grow the queue without bound.
+#[derive(Clone)]
+pub struct ModerationPolicy {
+    /// Where records await review
+    pub pending: Arc<dyn Store>,
+
+    /// Authors whose records are stored without review
+    pub trusted_authors: HashSet<PublicKey>,
+}
+
+impl ModerationPolicy {
+    /// Result code of submissions held for review: `NoConsumers`, which in
+    /// Mosaic says the server took the record but nobody will be served it
+    /// yet. Held records are served only once an operator releases them.
+    pub const PENDING_CODE: ResultCode = ResultCode::NoConsumers;
+
+    /// Hold records from every author not in `trusted_authors` in `pending`
+    pub fn new(
+        pending: Arc<dyn Store>,
+        trusted_authors: impl IntoIterator<Item = PublicKey>,
+    ) -> ModerationPolicy {
+        ModerationPolicy {
+            pending,
+            trusted_authors: trusted_authors.into_iter().collect(),
+        }
+    }
+
+    /// Whether records by `author` are stored without review
+    #[must_use]
+    pub fn trusts(&self, author: &PublicKey) -> bool {
+        self.trusted_authors.contains(author)
+    }
+
+    /// Put `record` in the queue
+    pub fn hold(&self, record: &Record) -> Result<PutResult, Error> {
+        self.pending.put_record(record)
+    }
+
+    /// The held record `id`, if there is one
+    pub fn held(&self, id: &Id) -> Result<Option<OwnedRecord>, Error> {
+        self.pending.get_record(&id.to_reference())
+    }
+
+    /// Up to `limit` held records in the order they arrived, starting just
+    /// after the record with id `after`
+    pub fn list(&self, after: Option<&Id>, limit: usize) -> Result<Vec<OwnedRecord>, Error> {
+        self.pending
+            .scan(ScanOrder::Insertion, after, limit)?
+            .iter()
+            .map(|entry| entry.record())
+            .collect()
+    }
+
+    /// Move the held record `id` into `store`. Returns `None` if no such
+    /// record is held. The record is not checked against any other policy;
+    /// `Server::release_pending` checks the blocklist and quotas first.
+    pub fn release(&self, store: &dyn Store, id: &Id) -> Result<Option<PutResult>, Error> {
+        let reference = id.to_reference();
+        let Some(record) = self.pending.get_record(&reference)? else {
+            return Ok(None);
+        };
+        let put = store.put_record(record.as_ref())?;
+        self.pending.delete_record(&reference)?;
+        Ok(Some(put))
+    }
+
+    /// Drop the held record `id`. Returns false if no such record is held.
+    pub fn reject(&self, id: &Id) -> Result<bool, Error> {
+        self.pending.delete_record(&id.to_reference())
+    }
+}
+
+impl fmt::Debug for ModerationPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ModerationPolicy")
+            .field("pending", &"<store>")
+            .field("trusted_authors", &self.trusted_authors.len())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::test_support::{InMemoryStore, build_record};
+
+    #[test]
+    fn held_records_are_released_or_rejected() {
+        let pending = Arc::new(InMemoryStore::default());
+        let store = InMemoryStore::default();
+        let moderation = ModerationPolicy::new(pending.clone(), []);
+        let good = build_record(b"worth publishing");
+        let bad = build_record(b"not worth publishing");
+        moderation.hold(good.as_ref()).unwrap();
+        moderation.hold(bad.as_ref()).unwrap();
+
+        let held = moderation.list(None, 10).unwrap();
+        assert_eq!(held.len(), 2);
+        assert_eq!(held[0].id(), good.id());
+
+        assert_eq!(
+            moderation.release(&store, &good.id()).unwrap(),
+            Some(PutResult::Inserted)
+        );
+        assert!(store.contains(good.id().as_bytes()));
+        assert!(moderation.reject(&bad.id()).unwrap());
+        assert_eq!(pending.record_count(), 0);
+        assert_eq!(store.record_count(), 1);
+
+        assert_eq!(moderation.release(&store, &bad.id()).unwrap(), None);
+        assert!(!moderation.reject(&bad.id()).unwrap());
+    }
+}
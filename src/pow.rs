@@ -0,0 +1,92 @@
+use std::collections::HashSet;
+use std::fmt;
+use std::sync::atomic::{AtomicU8, Ordering};
+
+use mosaic_core::{Id, PublicKey, ResultCode};
+
+/// A proof-of-work requirement on submitted records.
+///
+/// A record's work is the number of leading zero bits in the hash part of its
+/// id (the bytes after the 8-byte timestamp). Clients meet the target by
+/// varying a nonce tag until the id has enough. Records submitted by an
+/// authenticated peer and written by an exempt author need no work.
+///
+/// The difficulty can be changed while the server runs. The protocol has no
+/// way to announce it, so operators publish it alongside the server address;
+/// a client that does not know it can resubmit with more work each time its
+/// record is refused with `RESULT_CODE`.
+pub struct ProofOfWork {
+    difficulty: AtomicU8,
+    exempt_authors: HashSet<PublicKey>,
+}
+
+impl ProofOfWork {
+    /// Result code of records refused for insufficient work: the record does
+    /// not meet this server's requirements, and only a record with more work
+    /// will.
+    pub const RESULT_CODE: ResultCode = ResultCode::Invalid;
+
+    /// Require `difficulty` leading zero bits of records not written by one of
+    /// `exempt_authors`
+    pub fn new(difficulty: u8, exempt_authors: impl IntoIterator<Item = PublicKey>) -> ProofOfWork {
+        ProofOfWork {
+            difficulty: AtomicU8::new(difficulty),
+            exempt_authors: exempt_authors.into_iter().collect(),
+        }
+    }
+
+    /// Leading zero bits currently required
+    pub fn difficulty(&self) -> u8 {
+        self.difficulty.load(Ordering::Relaxed)
+    }
+
+    /// Change the leading zero bits required of records submitted from now on
+    pub fn set_difficulty(&self, difficulty: u8) {
+        self.difficulty.store(difficulty, Ordering::Relaxed);
+    }
+
+    /// Whether `author` is exempt when submitted by an authenticated peer
+    pub fn is_exempt(&self, author: &PublicKey) -> bool {
+        self.exempt_authors.contains(author)
+    }
+
+    /// Leading zero bits required of a record by `author` submitted by `peer`
+    pub fn required_bits(&self, peer: Option<PublicKey>, author: &PublicKey) -> u8 {
+        if peer.is_some() && self.is_exempt(author) {
+            0
+        } else {
+            self.difficulty()
+        }
+    }
+}
+
+impl Clone for ProofOfWork {
+    fn clone(&self) -> ProofOfWork {
+        ProofOfWork {
+            difficulty: AtomicU8::new(self.difficulty()),
+            exempt_authors: self.exempt_authors.clone(),
+        }
+    }
+}
+
+impl fmt::Debug for ProofOfWork {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ProofOfWork")
+            .field("difficulty", &self.difficulty())
+            .field("exempt_authors", &self.exempt_authors.len())
+            .finish()
+    }
+}
+
+/// The work in `id`: leading zero bits of the bytes after its timestamp
+#[must_use]
+pub fn work_bits(id: &Id) -> u32 {
+    let mut bits = 0;
+    for byte in &id.as_bytes()[8..] {
+        bits += byte.leading_zeros();
+        if *byte != 0 {
+            break;
+        }
+    }
+    bits
+}
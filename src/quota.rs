@@ -0,0 +1,135 @@
+use std::collections::HashMap;
+use std::sync::{Mutex, MutexGuard, PoisonError};
+
+use mosaic_core::{PublicKey, ResultCode};
+
+use crate::RecordTotals;
+
+/// Limits on how much a single author may store. `None` means unlimited.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Quota {
+    /// Most bytes of records the author may hold
+    pub max_bytes: Option<u64>,
+
+    /// Most records the author may hold
+    pub max_records: Option<u64>,
+}
+
+impl Quota {
+    /// True if storing one more record of `len` bytes on top of `used` would
+    /// exceed this quota
+    #[must_use]
+    pub fn would_exceed(&self, used: RecordTotals, len: u64) -> bool {
+        let bytes_over = self
+            .max_bytes
+            .is_some_and(|max| used.bytes.saturating_add(len) > max);
+        let records_over = self
+            .max_records
+            .is_some_and(|max| used.records.saturating_add(1) > max);
+        bytes_over || records_over
+    }
+}
+
+/// Per-author storage quotas: a default plus per-key overrides
+///
+/// Submissions that would take an author over quota are answered with
+/// `QuotaPolicy::RESULT_CODE`.
+#[derive(Debug, Clone, Default)]
+pub struct QuotaPolicy {
+    /// Quota for authors without an override
+    pub default: Quota,
+
+    /// Quotas for specific author keys
+    pub overrides: HashMap<PublicKey, Quota>,
+}
+
+impl QuotaPolicy {
+    /// The `ResultCode` sent when a submission is refused for exceeding quota:
+    /// the author's stored records would grow too large. A record within the
+    /// server's size limit that is refused with it has hit the quota.
+    pub const RESULT_CODE: ResultCode = ResultCode::TooLarge;
+
+    /// The quota that applies to `author`
+    #[must_use]
+    pub fn quota_for(&self, author: &PublicKey) -> Quota {
+        self.overrides.get(author).copied().unwrap_or(self.default)
+    }
+}
+
+/// Number of locks submissions are spread over by author
+const LOCK_STRIPES: usize = 64;
+
+/// Serializes the quota check and the store write of submissions by the same
+/// author, so concurrent submissions cannot together exceed the quota. Authors
+/// share a fixed number of locks, so memory does not grow with their number.
+pub(crate) struct QuotaLocks {
+    stripes: Vec<Mutex<()>>,
+}
+
+impl QuotaLocks {
+    /// Hold the lock for `author` until the guard is dropped
+    pub(crate) fn lock(&self, author: &PublicKey) -> MutexGuard<'_, ()> {
+        let stripe = usize::from(author.as_bytes()[0]) % self.stripes.len();
+        self.stripes[stripe]
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+impl Default for QuotaLocks {
+    fn default() -> QuotaLocks {
+        QuotaLocks {
+            stripes: (0..LOCK_STRIPES).map(|_| Mutex::new(())).collect(),
+        }
+    }
+}
+
+/// An author's storage compared to their quota
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct QuotaUsage {
+    /// What the author currently stores
+    pub used: RecordTotals,
+
+    /// The quota that applies to them
+    pub quota: Quota,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use mosaic_core::SecretKey;
+
+    #[test]
+    fn overrides_replace_the_default() {
+        let vip = SecretKey::generate().public();
+        let other = SecretKey::generate().public();
+        let policy = QuotaPolicy {
+            default: Quota {
+                max_bytes: Some(100),
+                max_records: None,
+            },
+            overrides: HashMap::from([(
+                vip,
+                Quota {
+                    max_bytes: None,
+                    max_records: Some(2),
+                },
+            )]),
+        };
+
+        let used = RecordTotals {
+            records: 1,
+            bytes: 90,
+        };
+        assert!(policy.quota_for(&other).would_exceed(used, 20));
+        assert!(!policy.quota_for(&other).would_exceed(used, 10));
+        assert!(!policy.quota_for(&vip).would_exceed(used, 1000));
+
+        let full = RecordTotals {
+            records: 2,
+            bytes: 0,
+        };
+        assert!(policy.quota_for(&vip).would_exceed(full, 1));
+    }
+}
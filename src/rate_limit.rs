@@ -0,0 +1,222 @@
+use std::net::IpAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+use dashmap::DashMap;
+use mosaic_core::{MessageType, PublicKey, ResultCode};
+
+/// Buckets idle for this long are full again and can be forgotten
+const IDLE_BUCKET_AGE: Duration = Duration::from_secs(10 * 60);
+
+/// Look for idle buckets once per this many checks
+const PRUNE_EVERY: u64 = 4096;
+
+/// A token bucket: up to `burst` requests at once, refilled at `per_second`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RateLimit {
+    /// Requests allowed back to back after a quiet period
+    pub burst: u32,
+
+    /// Sustained requests per second
+    pub per_second: f64,
+}
+
+/// Per-peer request rate limits, applied by IP address and, once the peer has
+/// authenticated, by public key as well
+#[derive(Debug, Clone, PartialEq)]
+pub struct RateLimitConfig {
+    /// Limit on SUBMISSION messages, if any
+    pub writes: Option<RateLimit>,
+
+    /// Limit on GET, QUERY and SUBSCRIBE messages, if any
+    pub reads: Option<RateLimit>,
+
+    /// Requests refused on one connection before it is closed
+    pub max_violations: u32,
+}
+
+impl RateLimitConfig {
+    /// Result code of refused requests and of the closing sent to repeat offenders
+    pub const RESULT_CODE: ResultCode = ResultCode::TooFast;
+}
+
+impl Default for RateLimitConfig {
+    fn default() -> RateLimitConfig {
+        RateLimitConfig {
+            writes: Some(RateLimit {
+                burst: 20,
+                per_second: 5.0,
+            }),
+            reads: Some(RateLimit {
+                burst: 100,
+                per_second: 50.0,
+            }),
+            max_violations: 20,
+        }
+    }
+}
+
+/// Which limit a message counts against
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub(crate) enum Traffic {
+    Read,
+    Write,
+}
+
+impl Traffic {
+    pub fn of(message_type: MessageType) -> Option<Traffic> {
+        match message_type {
+            MessageType::Submission => Some(Traffic::Write),
+            MessageType::Get | MessageType::Query | MessageType::Subscribe => Some(Traffic::Read),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum Peer {
+    Ip(IpAddr),
+    Key(PublicKey),
+}
+
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+impl Bucket {
+    fn refill(&mut self, limit: RateLimit, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.updated).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * limit.per_second).min(f64::from(limit.burst));
+        self.updated = now;
+    }
+}
+
+/// Token buckets shared by every connection
+pub(crate) struct RateLimiter {
+    config: RateLimitConfig,
+    buckets: DashMap<(Traffic, Peer), Bucket>,
+    checks: AtomicU64,
+}
+
+impl RateLimiter {
+    pub fn new(config: RateLimitConfig) -> RateLimiter {
+        RateLimiter {
+            config,
+            buckets: DashMap::new(),
+            checks: AtomicU64::new(0),
+        }
+    }
+
+    pub fn max_violations(&self) -> u32 {
+        self.config.max_violations
+    }
+
+    /// Take a token for one request from `ip` (and `peer`, if authenticated).
+    /// Returns false, taking nothing, if either bucket is empty.
+    pub fn allow(
+        &self,
+        traffic: Traffic,
+        ip: IpAddr,
+        peer: Option<PublicKey>,
+        now: Instant,
+    ) -> bool {
+        let limit = match traffic {
+            Traffic::Read => self.config.reads,
+            Traffic::Write => self.config.writes,
+        };
+        let Some(limit) = limit else {
+            return true;
+        };
+
+        if self.checks.fetch_add(1, Ordering::Relaxed) % PRUNE_EVERY == PRUNE_EVERY - 1 {
+            self.prune(now);
+        }
+
+        let keys: Vec<(Traffic, Peer)> = std::iter::once(Peer::Ip(ip))
+            .chain(peer.map(Peer::Key))
+            .map(|peer| (traffic, peer))
+            .collect();
+
+        // Refill every bucket first so a refusal from one takes nothing from the others.
+        let mut available = true;
+        for key in &keys {
+            let mut bucket = self.buckets.entry(*key).or_insert_with(|| Bucket {
+                tokens: f64::from(limit.burst),
+                updated: now,
+            });
+            bucket.refill(limit, now);
+            available &= bucket.tokens >= 1.0;
+        }
+        if !available {
+            return false;
+        }
+        for key in &keys {
+            if let Some(mut bucket) = self.buckets.get_mut(key) {
+                bucket.tokens -= 1.0;
+            }
+        }
+        true
+    }
+
+    /// Forget buckets that have not been used for a while
+    fn prune(&self, now: Instant) {
+        self.buckets
+            .retain(|_, bucket| now.saturating_duration_since(bucket.updated) < IDLE_BUCKET_AGE);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::net::Ipv4Addr;
+
+    use mosaic_core::SecretKey;
+
+    fn limiter() -> RateLimiter {
+        RateLimiter::new(RateLimitConfig {
+            writes: Some(RateLimit {
+                burst: 2,
+                per_second: 1.0,
+            }),
+            reads: None,
+            max_violations: 3,
+        })
+    }
+
+    #[test]
+    fn bucket_refills_over_time() {
+        let limiter = limiter();
+        let ip = IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1));
+        let start = Instant::now();
+
+        assert!(limiter.allow(Traffic::Write, ip, None, start));
+        assert!(limiter.allow(Traffic::Write, ip, None, start));
+        assert!(!limiter.allow(Traffic::Write, ip, None, start));
+        assert!(limiter.allow(Traffic::Read, ip, None, start));
+
+        let later = start + Duration::from_millis(1500);
+        assert!(limiter.allow(Traffic::Write, ip, None, later));
+        assert!(!limiter.allow(Traffic::Write, ip, None, later));
+
+        let other_ip = IpAddr::V4(Ipv4Addr::new(192, 0, 2, 2));
+        assert!(limiter.allow(Traffic::Write, other_ip, None, later));
+    }
+
+    #[test]
+    fn authenticated_peers_are_limited_across_addresses() {
+        let limiter = limiter();
+        let key = SecretKey::generate().public();
+        let now = Instant::now();
+        let first = IpAddr::V4(Ipv4Addr::new(198, 51, 100, 1));
+        let second = IpAddr::V4(Ipv4Addr::new(198, 51, 100, 2));
+
+        assert!(limiter.allow(Traffic::Write, first, Some(key), now));
+        assert!(limiter.allow(Traffic::Write, second, Some(key), now));
+        assert!(!limiter.allow(Traffic::Write, second, Some(key), now));
+
+        // The refusal took nothing from the second address's own bucket.
+        assert!(limiter.allow(Traffic::Write, second, None, now));
+    }
+}
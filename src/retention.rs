@@ -0,0 +1,429 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+
+use mosaic_core::{Id, Kind, PublicKey, Record, TagType, Timestamp};
+use tokio::sync::SetOnce;
+
+use crate::{DuplicateFilter, Error, Logger, ScanOrder, Store};
+
+/// Records read from the store per scan
+const SCAN_BATCH: usize = 256;
+
+/// Rules deciding when stored records expire
+#[derive(Debug, Clone)]
+pub struct RetentionPolicy {
+    /// Records of these kinds are deleted once they are older than the given age
+    pub max_age_by_kind: HashMap<Kind, Duration>,
+
+    /// Keep only this many of the newest records per author per kind
+    pub max_records_per_author_kind: Option<usize>,
+
+    /// Honour an expiration tag of this type: its data starts with a big-endian
+    /// `i64` nanosecond timestamp after which the record is deleted
+    pub expiration_tag_type: Option<TagType>,
+
+    /// Time between collection passes
+    pub interval: Duration,
+
+    /// Most records deleted in a single pass; the rest wait for the next one
+    pub max_deletions_per_pass: usize,
+}
+
+impl Default for RetentionPolicy {
+    fn default() -> RetentionPolicy {
+        RetentionPolicy {
+            max_age_by_kind: HashMap::new(),
+            max_records_per_author_kind: None,
+            expiration_tag_type: None,
+            interval: Duration::from_secs(60 * 60),
+            max_deletions_per_pass: 1000,
+        }
+    }
+}
+
+/// Why a record was removed by retention
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RetentionReason {
+    /// Older than the maximum age for its kind
+    MaxAge,
+
+    /// Beyond the per-author per-kind record limit
+    OverLimit,
+
+    /// Its expiration tag has passed
+    Expired,
+}
+
+/// Outcome of a retention pass
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RetentionReport {
+    /// Number of records examined
+    pub examined: u64,
+
+    /// Records deleted, and why
+    pub removed: Vec<(Id, RetentionReason)>,
+
+    /// Records that are due for removal but were left for a later pass
+    pub deferred: u64,
+}
+
+/// Run one retention pass over `store`, as of `now`.
+///
+/// Walks every record oldest first, deleting those past their maximum age or
+/// expiration as it goes. If a per-author limit is set, a second walk then
+/// deletes the oldest records of each author and kind over the limit. At most
+/// `policy.max_deletions_per_pass` records are deleted; the rest are counted
+/// as deferred. Memory use grows with the number of authors and kinds, not
+/// with the number of records.
+pub fn collect_garbage(
+    store: &dyn Store,
+    policy: &RetentionPolicy,
+    now: Timestamp,
+) -> Result<RetentionReport, Error> {
+    let mut pass = RetentionPass::new(policy.clone(), now);
+    while !pass.step(store)? {}
+    Ok(pass.report)
+}
+
+/// A retention pass that advances one scan batch at a time, so it can be
+/// stopped between batches
+struct RetentionPass {
+    policy: RetentionPolicy,
+    now: i64,
+    phase: Phase,
+    after: Option<Id>,
+    // Per author and kind, records not otherwise expiring; once trimming
+    // starts, how many of the oldest are still to be removed
+    counts: HashMap<(PublicKey, Kind), usize>,
+    report: RetentionReport,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Phase {
+    Expiring,
+    Trimming,
+}
+
+impl RetentionPass {
+    fn new(policy: RetentionPolicy, now: Timestamp) -> RetentionPass {
+        RetentionPass {
+            policy,
+            now: now.as_nanoseconds(),
+            phase: Phase::Expiring,
+            after: None,
+            counts: HashMap::new(),
+            report: RetentionReport::default(),
+        }
+    }
+
+    /// Process the next batch of records, returning true once the pass is done
+    fn step(&mut self, store: &dyn Store) -> Result<bool, Error> {
+        let batch = store.scan(ScanOrder::Timestamp, self.after.as_ref(), SCAN_BATCH)?;
+        let Some(last) = batch.last() else {
+            let Some(max) = self.policy.max_records_per_author_kind else {
+                return Ok(true);
+            };
+            if self.phase == Phase::Trimming {
+                return Ok(true);
+            }
+            self.phase = Phase::Trimming;
+            self.after = None;
+            self.counts.retain(|_, count| {
+                *count = count.saturating_sub(max);
+                *count > 0
+            });
+            return Ok(self.counts.is_empty());
+        };
+        self.after = Some(last.id);
+
+        for entry in &batch {
+            if self.phase == Phase::Expiring {
+                self.report.examined += 1;
+            }
+            // Records that fail verification are left for `check_store`
+            let Ok(record) = entry.record() else {
+                continue;
+            };
+            let expiring = expiry_reason(&self.policy, record.as_ref(), self.now);
+            let key = (record.author_public_key(), record.kind());
+
+            match (self.phase, expiring) {
+                (Phase::Expiring, Some(reason)) => self.remove(store, entry.id, reason)?,
+                (Phase::Expiring, None) => {
+                    if self.policy.max_records_per_author_kind.is_some() {
+                        *self.counts.entry(key).or_default() += 1;
+                    }
+                }
+                (Phase::Trimming, Some(_)) => {}
+                (Phase::Trimming, None) => {
+                    if let Some(excess) = self.counts.get_mut(&key) {
+                        *excess -= 1;
+                        if *excess == 0 {
+                            self.counts.remove(&key);
+                        }
+                        self.remove(store, entry.id, RetentionReason::OverLimit)?;
+                    }
+                }
+            }
+        }
+        Ok(false)
+    }
+
+    fn remove(&mut self, store: &dyn Store, id: Id, reason: RetentionReason) -> Result<(), Error> {
+        if self.report.removed.len() >= self.policy.max_deletions_per_pass {
+            self.report.deferred += 1;
+        } else if store.delete_record(&id.to_reference())? {
+            self.report.removed.push((id, reason));
+        }
+        Ok(())
+    }
+}
+
+fn expiry_reason(policy: &RetentionPolicy, record: &Record, now: i64) -> Option<RetentionReason> {
+    if let Some(max_age) = policy.max_age_by_kind.get(&record.kind()) {
+        let age = now.saturating_sub(record.timestamp().as_nanoseconds());
+        if u128::try_from(age).is_ok_and(|age| age > max_age.as_nanos()) {
+            return Some(RetentionReason::MaxAge);
+        }
+    }
+
+    if let Some(tag_type) = policy.expiration_tag_type {
+        let expires = record
+            .tag_set()
+            .iter()
+            .find(|tag| tag.get_type() == tag_type)
+            .and_then(|tag| tag.data_bytes().get(..8)?.try_into().ok())
+            .map(i64::from_be_bytes);
+        if expires.is_some_and(|expires| expires <= now) {
+            return Some(RetentionReason::Expired);
+        }
+    }
+
+    None
+}
+
+/// Run retention passes every `policy.interval` until shutdown starts,
+/// keeping the duplicate filter (if any) in step with the deletions. A pass
+/// in progress stops at the end of its current batch once shutdown starts.
+pub(crate) async fn run_retention<L: Logger + 'static>(
+    store: Arc<dyn Store>,
+    policy: Arc<RetentionPolicy>,
+    duplicates: Option<Arc<DuplicateFilter>>,
+    logger: Arc<L>,
+    shutting_down: Arc<SetOnce<u32>>,
+) {
+    loop {
+        tokio::select! {
+            _ = tokio::time::sleep(policy.interval) => {},
+            _ = shutting_down.wait() => break,
+        }
+
+        let mut pass = match Timestamp::now() {
+            Ok(now) => Some(RetentionPass::new((*policy).clone(), now)),
+            Err(e) => {
+                logger.log_server_error(e.into());
+                continue;
+            }
+        };
+        while let Some(mut current) = pass.take() {
+            if shutting_down.initialized() {
+                pass = Some(current);
+                break;
+            }
+            let store2 = store.clone();
+            let duplicates2 = duplicates.clone();
+            // Evict each batch's deletions as it is made, so they are not lost
+            // with the pass if this task fails
+            let step = tokio::task::spawn_blocking(move || {
+                let before = current.report.removed.len();
+                let done = current.step(&*store2);
+                if let Some(filter) = &duplicates2 {
+                    for (id, _) in &current.report.removed[before..] {
+                        filter.remove(id);
+                    }
+                }
+                (current, done)
+            })
+            .await;
+            match step {
+                Ok((current, Ok(false))) => pass = Some(current),
+                Ok((current, Ok(true))) => {
+                    pass = Some(current);
+                    break;
+                }
+                Ok((current, Err(e))) => {
+                    logger.log_server_error(e);
+                    pass = Some(current);
+                    break;
+                }
+                Err(e) => logger.log_server_error(e.into()),
+            }
+        }
+
+        // Deletions made before a failure or shutdown still count
+        let Some(RetentionPass { report, .. }) = pass else {
+            continue;
+        };
+        if !report.removed.is_empty() {
+            logger.log_retention(&report);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use mosaic_core::SecretKey;
+
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use crate::test_support::{InMemoryStore, build_record_with, timestamp_ago};
+    use crate::{Fault, FaultyStore, StoreErrorKind, StoreMethod};
+
+    const HOUR: Duration = Duration::from_secs(60 * 60);
+
+    #[test]
+    fn old_records_of_limited_kinds_are_removed() {
+        let store = InMemoryStore::default();
+        let key = SecretKey::generate();
+        let old = build_record_with(&key, Kind::CHAT_MESSAGE, timestamp_ago(2 * HOUR), b"old");
+        let fresh = build_record_with(&key, Kind::CHAT_MESSAGE, timestamp_ago(HOUR / 2), b"new");
+        let other_kind =
+            build_record_with(&key, Kind::KEY_SCHEDULE, timestamp_ago(2 * HOUR), b"keep");
+        for record in [&old, &fresh, &other_kind] {
+            store.put_record(record.as_ref()).unwrap();
+        }
+
+        let policy = RetentionPolicy {
+            max_age_by_kind: HashMap::from([(Kind::CHAT_MESSAGE, HOUR)]),
+            ..Default::default()
+        };
+        let report = collect_garbage(&store, &policy, Timestamp::now().unwrap()).unwrap();
+
+        assert_eq!(report.examined, 3);
+        assert_eq!(report.removed, vec![(old.id(), RetentionReason::MaxAge)]);
+        assert_eq!(store.record_count(), 2);
+    }
+
+    #[test]
+    fn per_author_limit_keeps_newest_within_pass_budget() {
+        let store = InMemoryStore::default();
+        let key = SecretKey::generate();
+        let records: Vec<_> = (1..=4_u32)
+            .rev()
+            .map(|hours| {
+                build_record_with(&key, Kind::CHAT_MESSAGE, timestamp_ago(hours * HOUR), b"x")
+            })
+            .collect();
+        for record in &records {
+            store.put_record(record.as_ref()).unwrap();
+        }
+
+        let policy = RetentionPolicy {
+            max_records_per_author_kind: Some(1),
+            max_deletions_per_pass: 2,
+            ..Default::default()
+        };
+
+        let first = collect_garbage(&store, &policy, Timestamp::now().unwrap()).unwrap();
+        assert_eq!(
+            first.removed,
+            vec![
+                (records[0].id(), RetentionReason::OverLimit),
+                (records[1].id(), RetentionReason::OverLimit)
+            ]
+        );
+        assert_eq!(first.deferred, 1);
+
+        let second = collect_garbage(&store, &policy, Timestamp::now().unwrap()).unwrap();
+        assert_eq!(
+            second.removed,
+            vec![(records[2].id(), RetentionReason::OverLimit)]
+        );
+        assert_eq!(store.record_count(), 1);
+        assert!(store.contains(records[3].id().as_bytes()));
+    }
+
+    #[test]
+    fn a_pass_advances_one_batch_per_step() {
+        let store = InMemoryStore::default();
+        let key = SecretKey::generate();
+        for i in 0..SCAN_BATCH + 10 {
+            let record = build_record_with(
+                &key,
+                Kind::CHAT_MESSAGE,
+                timestamp_ago(2 * HOUR),
+                &i.to_be_bytes(),
+            );
+            store.put_record(record.as_ref()).unwrap();
+        }
+
+        let policy = RetentionPolicy {
+            max_age_by_kind: HashMap::from([(Kind::CHAT_MESSAGE, HOUR)]),
+            ..Default::default()
+        };
+        let mut pass = RetentionPass::new(policy, Timestamp::now().unwrap());
+        assert!(!pass.step(&store).unwrap());
+        assert_eq!(pass.report.examined, SCAN_BATCH as u64);
+        assert_eq!(pass.report.removed.len(), SCAN_BATCH);
+        assert_eq!(store.record_count(), 10);
+
+        while !pass.step(&store).unwrap() {}
+        assert_eq!(pass.report.removed.len(), SCAN_BATCH + 10);
+        assert_eq!(store.record_count(), 0);
+    }
+
+    #[derive(Default)]
+    struct CountingLogger {
+        server_errors: AtomicUsize,
+    }
+
+    impl Logger for CountingLogger {
+        fn log_client_error(
+            &self,
+            _e: Error,
+            _socket_addr: std::net::SocketAddr,
+            _pubkey: Option<PublicKey>,
+        ) {
+        }
+
+        fn log_server_error(&self, _e: Error) {
+            self.server_errors.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    #[tokio::test]
+    async fn retention_task_stops_on_shutdown_while_the_store_fails() {
+        let store = FaultyStore::new(InMemoryStore::default(), 3).with_fault(
+            StoreMethod::Scan,
+            Fault {
+                latency: Duration::from_millis(5),
+                ..Fault::always(StoreErrorKind::Io)
+            },
+        );
+        let policy = RetentionPolicy {
+            interval: Duration::from_millis(10),
+            ..Default::default()
+        };
+        let logger = Arc::new(CountingLogger::default());
+        let shutting_down = Arc::new(SetOnce::new());
+        let task = tokio::spawn(run_retention(
+            Arc::new(store),
+            Arc::new(policy),
+            None,
+            logger.clone(),
+            shutting_down.clone(),
+        ));
+
+        while logger.server_errors.load(Ordering::Relaxed) < 2 {
+            tokio::time::sleep(Duration::from_millis(5)).await;
+        }
+        shutting_down.set(0).unwrap();
+        tokio::time::timeout(Duration::from_secs(5), task)
+            .await
+            .expect("retention task did not stop")
+            .unwrap();
+    }
+}
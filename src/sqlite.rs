@@ -0,0 +1,558 @@
+use std::path::Path;
+use std::sync::{Mutex, MutexGuard, PoisonError};
+
+use mosaic_core::{Id, Kind, OwnedRecord, PublicKey, Record, Reference};
+use rusqlite::{Connection, ErrorCode, OptionalExtension, Transaction, params};
+
+use crate::{
+    Error, PutResult, RecordTotals, ScanEntry, ScanOrder, Store, StoreError, StoreErrorKind,
+    StoreStats,
+};
+
+/// File name of the snapshot written by `SqliteStore::backup_to`
+const BACKUP_FILE: &str = "mosaic.sqlite";
+
+const SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS records (
+        seq INTEGER PRIMARY KEY AUTOINCREMENT,
+        id BLOB NOT NULL UNIQUE,
+        address BLOB NOT NULL,
+        author BLOB NOT NULL,
+        kind INTEGER NOT NULL,
+        timestamp INTEGER NOT NULL,
+        bytes BLOB NOT NULL
+    );
+    CREATE INDEX IF NOT EXISTS records_by_address ON records (address, timestamp);
+    CREATE INDEX IF NOT EXISTS records_by_author ON records (author, kind);
+    CREATE INDEX IF NOT EXISTS records_by_kind ON records (kind, timestamp);
+    CREATE INDEX IF NOT EXISTS records_by_timestamp ON records (timestamp);
+
+    CREATE TABLE IF NOT EXISTS tags (
+        seq INTEGER NOT NULL,
+        tag_type INTEGER NOT NULL,
+        data BLOB NOT NULL
+    );
+    CREATE INDEX IF NOT EXISTS tags_by_value ON tags (tag_type, data);
+    CREATE INDEX IF NOT EXISTS tags_by_record ON tags (seq);
+
+    -- Sequence numbers of deleted records, so scan cursors survive deletes
+    CREATE TABLE IF NOT EXISTS tombstones (
+        id BLOB PRIMARY KEY,
+        seq INTEGER NOT NULL
+    );
+
+    -- Statistics counters: the total, then per kind and per author
+    CREATE TABLE IF NOT EXISTS stats (
+        key BLOB PRIMARY KEY,
+        records INTEGER NOT NULL,
+        bytes INTEGER NOT NULL
+    );
+";
+
+const STATS_TOTAL_KEY: &[u8] = b"t";
+const STATS_KIND_PREFIX: u8 = b'k';
+const STATS_AUTHOR_PREFIX: u8 = b'a';
+
+/// SQLite-backed store kept in a single database file.
+///
+/// Records are held with their id, address, author, kind and timestamp in
+/// indexed columns, and their tags in a separate indexed table, so the file can
+/// be inspected with ordinary SQL. Statistics are kept in a table updated in
+/// the same transaction as every insert and delete, and mirrored in memory; a
+/// database without them has them counted from its records when first opened.
+pub struct SqliteStore {
+    conn: Mutex<Connection>,
+    stats: Mutex<StoreStats>,
+}
+
+impl SqliteStore {
+    /// Open or create a SQLite-backed store in the file at `path`.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, Error> {
+        let conn = Connection::open(path).map_err(convert_sqlite_error)?;
+        Self::from_connection(conn)
+    }
+
+    /// Create a store that lives only in memory, for tests and tools.
+    pub fn open_in_memory() -> Result<Self, Error> {
+        let conn = Connection::open_in_memory().map_err(convert_sqlite_error)?;
+        Self::from_connection(conn)
+    }
+
+    fn from_connection(conn: Connection) -> Result<Self, Error> {
+        conn.execute_batch("PRAGMA journal_mode = WAL; PRAGMA synchronous = NORMAL;")
+            .map_err(convert_sqlite_error)?;
+        conn.execute_batch(SCHEMA).map_err(convert_sqlite_error)?;
+        let stats = match load_stats(&conn).map_err(convert_sqlite_error)? {
+            Some(stats) => stats,
+            None => rebuild_stats(&conn).map_err(convert_sqlite_error)?,
+        };
+        Ok(Self {
+            conn: Mutex::new(conn),
+            stats: Mutex::new(stats),
+        })
+    }
+
+    fn conn(&self) -> MutexGuard<'_, Connection> {
+        self.conn.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn lock_stats(&self) -> MutexGuard<'_, StoreStats> {
+        self.stats.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+impl Store for SqliteStore {
+    fn put_record(&self, record: &Record) -> Result<PutResult, Error> {
+        let mut conn = self.conn();
+        let txn = conn.transaction().map_err(convert_sqlite_error)?;
+        let inserted = txn
+            .execute(
+                "INSERT OR IGNORE INTO records (id, address, author, kind, timestamp, bytes)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+                params![
+                    record.id().as_bytes().as_slice(),
+                    record.address().to_reference().as_bytes().as_slice(),
+                    record.author_public_key().as_bytes().as_slice(),
+                    record.kind().to_u64() as i64,
+                    record.timestamp().as_nanoseconds(),
+                    record.as_bytes(),
+                ],
+            )
+            .map_err(convert_sqlite_error)?;
+        if inserted == 0 {
+            return Ok(PutResult::Duplicate);
+        }
+
+        let seq = txn.last_insert_rowid();
+        {
+            let mut insert_tag = txn
+                .prepare_cached("INSERT INTO tags (seq, tag_type, data) VALUES (?1, ?2, ?3)")
+                .map_err(convert_sqlite_error)?;
+            for tag in record.tag_set().iter() {
+                insert_tag
+                    .execute(params![seq, tag.get_type().0, tag.data_bytes()])
+                    .map_err(convert_sqlite_error)?;
+            }
+        }
+        update_persisted_stats(&txn, record, 1).map_err(convert_sqlite_error)?;
+        txn.commit().map_err(convert_sqlite_error)?;
+
+        self.lock_stats().record_inserted(record);
+        Ok(PutResult::Inserted)
+    }
+
+    fn has_record(&self, reference: &Reference) -> Result<bool, Error> {
+        let conn = self.conn();
+        let key = reference.as_bytes().as_slice();
+        conn.query_row(
+            "SELECT EXISTS (SELECT 1 FROM records WHERE id = ?1)
+                 OR EXISTS (SELECT 1 FROM records WHERE address = ?1)",
+            [key],
+            |row| row.get(0),
+        )
+        .map_err(convert_sqlite_error)
+    }
+
+    fn get_record(&self, reference: &Reference) -> Result<Option<OwnedRecord>, Error> {
+        let conn = self.conn();
+        let key = reference.as_bytes().as_slice();
+        let by_id = conn
+            .query_row("SELECT bytes FROM records WHERE id = ?1", [key], |row| {
+                row.get::<_, Vec<u8>>(0)
+            })
+            .optional()
+            .map_err(convert_sqlite_error)?;
+        // Address references resolve to the newest version at that address
+        let bytes = match by_id {
+            Some(bytes) => Some(bytes),
+            None => conn
+                .query_row(
+                    "SELECT bytes FROM records WHERE address = ?1
+                     ORDER BY timestamp DESC, id DESC LIMIT 1",
+                    [key],
+                    |row| row.get::<_, Vec<u8>>(0),
+                )
+                .optional()
+                .map_err(convert_sqlite_error)?,
+        };
+
+        match bytes {
+            Some(bytes) => {
+                let owned = OwnedRecord::from_vec(bytes)
+                    .map_err(|e| StoreError::new(StoreErrorKind::Corruption, e))?;
+                Ok(Some(owned))
+            }
+            None => Ok(None),
+        }
+    }
+
+    fn stats(&self) -> Result<StoreStats, Error> {
+        Ok(self.lock_stats().clone())
+    }
+
+    fn author_stats(&self, author: &PublicKey) -> Result<RecordTotals, Error> {
+        Ok(self
+            .lock_stats()
+            .by_author
+            .get(author)
+            .copied()
+            .unwrap_or_default())
+    }
+
+    fn delete_record(&self, reference: &Reference) -> Result<bool, Error> {
+        let Some(record) = self.get_record(reference)? else {
+            return Ok(false);
+        };
+
+        let mut conn = self.conn();
+        let txn = conn.transaction().map_err(convert_sqlite_error)?;
+        let id = record.id();
+        let seq: Option<i64> = txn
+            .query_row(
+                "SELECT seq FROM records WHERE id = ?1",
+                [id.as_bytes().as_slice()],
+                |row| row.get(0),
+            )
+            .optional()
+            .map_err(convert_sqlite_error)?;
+        let Some(seq) = seq else {
+            // Deleted by someone else since we looked it up
+            return Ok(false);
+        };
+        txn.execute("DELETE FROM records WHERE seq = ?1", [seq])
+            .map_err(convert_sqlite_error)?;
+        txn.execute("DELETE FROM tags WHERE seq = ?1", [seq])
+            .map_err(convert_sqlite_error)?;
+        txn.execute(
+            "INSERT OR REPLACE INTO tombstones (id, seq) VALUES (?1, ?2)",
+            params![id.as_bytes().as_slice(), seq],
+        )
+        .map_err(convert_sqlite_error)?;
+        update_persisted_stats(&txn, record.as_ref(), -1).map_err(convert_sqlite_error)?;
+        txn.commit().map_err(convert_sqlite_error)?;
+
+        self.lock_stats().record_removed(record.as_ref());
+        Ok(true)
+    }
+
+    fn scan(
+        &self,
+        order: ScanOrder,
+        after: Option<&Id>,
+        limit: usize,
+    ) -> Result<Vec<ScanEntry>, Error> {
+        let conn = self.conn();
+        let limit = i64::try_from(limit).unwrap_or(i64::MAX);
+        let rows = match order {
+            ScanOrder::Insertion => {
+                let start = match after {
+                    Some(id) => cursor_sequence(&conn, id)?,
+                    None => 0,
+                };
+                let mut stmt = conn
+                    .prepare_cached(
+                        "SELECT id, bytes FROM records WHERE seq > ?1 ORDER BY seq LIMIT ?2",
+                    )
+                    .map_err(convert_sqlite_error)?;
+                stmt.query_map(params![start, limit], read_entry)
+                    .and_then(|rows| rows.collect::<Result<Vec<_>, _>>())
+                    .map_err(convert_sqlite_error)?
+            }
+            ScanOrder::Timestamp => {
+                // Ids begin with the timestamp, so id order is timestamp order
+                let lower: &[u8] = after.map_or(&[], |id| id.as_bytes().as_slice());
+                let mut stmt = conn
+                    .prepare_cached(
+                        "SELECT id, bytes FROM records WHERE id > ?1 ORDER BY id LIMIT ?2",
+                    )
+                    .map_err(convert_sqlite_error)?;
+                stmt.query_map(params![lower, limit], read_entry)
+                    .and_then(|rows| rows.collect::<Result<Vec<_>, _>>())
+                    .map_err(convert_sqlite_error)?
+            }
+        };
+
+        rows.into_iter()
+            .map(|(id, bytes)| {
+                let id = decode_id(&id).ok_or_else(|| {
+                    StoreError::new(StoreErrorKind::Corruption, "stored id is not 48 bytes")
+                })?;
+                Ok(ScanEntry { id, bytes })
+            })
+            .collect()
+    }
+
+    /// Write a compacted copy of the database to `mosaic.sqlite` in `path`.
+    ///
+    /// Writers wait while the copy is made.
+    fn backup_to(&self, path: &Path) -> Result<(), Error> {
+        let io_error = |e| StoreError::new(StoreErrorKind::Io, e);
+        std::fs::create_dir_all(path).map_err(io_error)?;
+        let target = path.join(BACKUP_FILE);
+        if target.exists() {
+            return Err(io_error(std::io::Error::new(
+                std::io::ErrorKind::AlreadyExists,
+                format!("{} already exists", target.display()),
+            ))
+            .into());
+        }
+
+        self.conn()
+            .execute("VACUUM INTO ?1", [target.to_string_lossy()])
+            .map_err(convert_sqlite_error)?;
+        Ok(())
+    }
+}
+
+/// The insertion sequence number of the record with `id`, deleted or not
+fn cursor_sequence(conn: &Connection, id: &Id) -> Result<i64, Error> {
+    let seq: Option<i64> = conn
+        .query_row(
+            "SELECT seq FROM records WHERE id = ?1
+             UNION ALL SELECT seq FROM tombstones WHERE id = ?1
+             LIMIT 1",
+            [id.as_bytes().as_slice()],
+            |row| row.get(0),
+        )
+        .optional()
+        .map_err(convert_sqlite_error)?;
+    seq.ok_or_else(|| {
+        StoreError::new(
+            StoreErrorKind::Other,
+            "scan cursor is not a known record id",
+        )
+        .into()
+    })
+}
+
+fn read_entry(row: &rusqlite::Row<'_>) -> rusqlite::Result<(Vec<u8>, Vec<u8>)> {
+    Ok((row.get(0)?, row.get(1)?))
+}
+
+/// Read the persisted statistics. Returns `None` if none have been persisted.
+fn load_stats(conn: &Connection) -> rusqlite::Result<Option<StoreStats>> {
+    let mut stats = StoreStats::default();
+    let mut found_total = false;
+
+    let mut stmt = conn.prepare("SELECT key, records, bytes FROM stats")?;
+    let rows = stmt.query_map([], |row| Ok((row.get::<_, Vec<u8>>(0)?, read_totals(row)?)))?;
+    for row in rows {
+        let (key, totals) = row?;
+        match key.split_first() {
+            Some((&STATS_KIND_PREFIX, kind)) => {
+                if let Ok(kind) = <[u8; 8]>::try_from(kind) {
+                    stats
+                        .by_kind
+                        .insert(Kind::from_u64(u64::from_be_bytes(kind)), totals);
+                }
+            }
+            Some((&STATS_AUTHOR_PREFIX, author)) => {
+                if let Some(author) = decode_author(author) {
+                    stats.by_author.insert(author, totals);
+                }
+            }
+            _ if key == STATS_TOTAL_KEY => {
+                stats.total = totals;
+                found_total = true;
+            }
+            _ => {}
+        }
+    }
+
+    Ok(found_total.then_some(stats))
+}
+
+/// Count the statistics from the records themselves and persist them.
+fn rebuild_stats(conn: &Connection) -> rusqlite::Result<StoreStats> {
+    let mut stats = StoreStats::default();
+
+    let mut by_kind = conn.prepare(
+        "SELECT kind, COUNT(*), COALESCE(SUM(LENGTH(bytes)), 0) FROM records GROUP BY kind",
+    )?;
+    let rows = by_kind.query_map([], |row| Ok((row.get::<_, i64>(0)?, read_totals(row)?)))?;
+    for row in rows {
+        let (kind, totals) = row?;
+        stats.by_kind.insert(Kind::from_u64(kind as u64), totals);
+        stats.total.records += totals.records;
+        stats.total.bytes += totals.bytes;
+    }
+
+    let mut by_author = conn.prepare(
+        "SELECT author, COUNT(*), COALESCE(SUM(LENGTH(bytes)), 0) FROM records GROUP BY author",
+    )?;
+    let rows = by_author.query_map([], |row| Ok((row.get::<_, Vec<u8>>(0)?, read_totals(row)?)))?;
+    for row in rows {
+        let (author, totals) = row?;
+        if let Some(author) = decode_author(&author) {
+            stats.by_author.insert(author, totals);
+        }
+    }
+
+    let mut insert =
+        conn.prepare("INSERT OR REPLACE INTO stats (key, records, bytes) VALUES (?1, ?2, ?3)")?;
+    let mut persist = |key: &[u8], totals: RecordTotals| {
+        insert.execute(params![key, totals.records as i64, totals.bytes as i64])
+    };
+    persist(STATS_TOTAL_KEY, stats.total)?;
+    for (kind, totals) in &stats.by_kind {
+        persist(&kind_stats_key(*kind), *totals)?;
+    }
+    for (author, totals) in &stats.by_author {
+        persist(&author_stats_key(author), *totals)?;
+    }
+
+    Ok(stats)
+}
+
+/// Add (`sign` 1) or remove (`sign` -1) a record's share of the persisted
+/// totals. The total row is kept even at zero to mark the stats as present.
+fn update_persisted_stats(
+    txn: &Transaction<'_>,
+    record: &Record,
+    sign: i64,
+) -> rusqlite::Result<()> {
+    let len = record.as_bytes().len() as i64;
+    let kind_key = kind_stats_key(record.kind());
+    let author_key = author_stats_key(&record.author_public_key());
+
+    let mut update = txn.prepare_cached(
+        "INSERT INTO stats (key, records, bytes) VALUES (?1, ?2, ?3)
+         ON CONFLICT (key) DO UPDATE SET records = records + ?2, bytes = bytes + ?3",
+    )?;
+    for key in [STATS_TOTAL_KEY, kind_key.as_slice(), author_key.as_slice()] {
+        update.execute(params![key, sign, sign * len])?;
+    }
+    txn.execute(
+        "DELETE FROM stats WHERE records <= 0 AND key != ?1",
+        [STATS_TOTAL_KEY],
+    )?;
+    Ok(())
+}
+
+fn kind_stats_key(kind: Kind) -> [u8; 9] {
+    let mut key = [STATS_KIND_PREFIX; 9];
+    key[1..].copy_from_slice(&kind.to_u64().to_be_bytes());
+    key
+}
+
+fn author_stats_key(author: &PublicKey) -> [u8; 33] {
+    let mut key = [STATS_AUTHOR_PREFIX; 33];
+    key[1..].copy_from_slice(author.as_bytes());
+    key
+}
+
+fn decode_author(bytes: &[u8]) -> Option<PublicKey> {
+    let bytes: &[u8; 32] = bytes.try_into().ok()?;
+    PublicKey::from_bytes(bytes).ok()
+}
+
+/// Read a count and byte total from columns 1 and 2
+fn read_totals(row: &rusqlite::Row<'_>) -> rusqlite::Result<RecordTotals> {
+    Ok(RecordTotals {
+        records: row.get::<_, i64>(1)? as u64,
+        bytes: row.get::<_, i64>(2)? as u64,
+    })
+}
+
+fn decode_id(bytes: &[u8]) -> Option<Id> {
+    let bytes: &[u8; 48] = bytes.try_into().ok()?;
+    Id::from_bytes(bytes).ok()
+}
+
+fn convert_sqlite_error(error: rusqlite::Error) -> Error {
+    StoreError::new(classify_sqlite_error(&error), error).into()
+}
+
+fn classify_sqlite_error(error: &rusqlite::Error) -> StoreErrorKind {
+    match error {
+        rusqlite::Error::SqliteFailure(e, _) => match e.code {
+            ErrorCode::DiskFull => StoreErrorKind::OutOfSpace,
+            ErrorCode::DatabaseCorrupt | ErrorCode::NotADatabase => StoreErrorKind::Corruption,
+            ErrorCode::SystemIoFailure | ErrorCode::CannotOpen | ErrorCode::PermissionDenied => {
+                StoreErrorKind::Io
+            }
+            ErrorCode::DatabaseBusy | ErrorCode::DatabaseLocked | ErrorCode::OutOfMemory => {
+                StoreErrorKind::Transient
+            }
+            _ => StoreErrorKind::Other,
+        },
+        rusqlite::Error::FromSqlConversionFailure(..) | rusqlite::Error::InvalidColumnType(..) => {
+            StoreErrorKind::Corruption
+        }
+        _ => StoreErrorKind::Other,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::test_support::{build_record, conformance};
+
+    #[test]
+    fn conforms_to_the_store_contract() {
+        conformance::check_store(|| SqliteStore::open_in_memory().unwrap());
+
+        let temp_dir = tempfile::tempdir().unwrap();
+        let path = temp_dir.path().join("store.sqlite");
+        conformance::check_stats_persist(|| SqliteStore::open(&path).unwrap());
+    }
+
+    #[test]
+    fn missing_stats_are_rebuilt_on_open() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let path = temp_dir.path().join("store.sqlite");
+        let expected = {
+            let store = SqliteStore::open(&path).unwrap();
+            for i in 0..3 {
+                let record = build_record(format!("record {i}").as_bytes());
+                store.put_record(record.as_ref()).unwrap();
+            }
+            // Forget the statistics, as in a database written before they were kept.
+            store.conn().execute("DELETE FROM stats", []).unwrap();
+            store.stats().unwrap()
+        };
+
+        let reopened = SqliteStore::open(&path).unwrap();
+        assert_eq!(reopened.stats().unwrap(), expected);
+        assert_eq!(expected.total.records, 3);
+    }
+
+    #[test]
+    fn backend_errors_are_classified() {
+        let busy = rusqlite::Error::SqliteFailure(
+            rusqlite::ffi::Error::new(rusqlite::ffi::SQLITE_BUSY),
+            None,
+        );
+        assert_eq!(classify_sqlite_error(&busy), StoreErrorKind::Transient);
+
+        let full = rusqlite::Error::SqliteFailure(
+            rusqlite::ffi::Error::new(rusqlite::ffi::SQLITE_FULL),
+            None,
+        );
+        assert_eq!(classify_sqlite_error(&full), StoreErrorKind::OutOfSpace);
+
+        let converted = convert_sqlite_error(busy);
+        let crate::InnerError::Store(store_error) = &converted.inner else {
+            panic!("expected a store error, got {converted}");
+        };
+        assert_eq!(store_error.kind(), StoreErrorKind::Transient);
+        assert!(std::error::Error::source(store_error).is_some());
+    }
+
+    #[test]
+    fn backup_opens_as_a_store() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let store = SqliteStore::open(temp_dir.path().join("live.sqlite")).unwrap();
+        let record = build_record(b"backed up");
+        store.put_record(record.as_ref()).unwrap();
+
+        let backup_dir = temp_dir.path().join("backup");
+        store.backup_to(&backup_dir).unwrap();
+        assert!(store.backup_to(&backup_dir).is_err());
+
+        let restored = SqliteStore::open(backup_dir.join(BACKUP_FILE)).unwrap();
+        assert!(restored.has_record(&record.id().to_reference()).unwrap());
+        assert_eq!(restored.stats().unwrap().total.records, 1);
+    }
+}
@@ -1,9 +1,10 @@
+use std::collections::HashMap;
+use std::error::Error as StdError;
 use std::path::Path;
 
-use mosaic_core::{OwnedRecord, Record, Reference};
-use mosaic_store_lmdb::{InnerError as LmdbInnerError, Store as RawLmdbStore};
+use mosaic_core::{Id, Kind, OwnedRecord, PublicKey, Record, Reference, ResultCode};
 
-use crate::{Error, InnerError};
+use crate::{Error, FsckProblem};
 
 /// Result of attempting to insert a record into storage
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -22,103 +23,251 @@ pub trait Store: Send + Sync {
 
     /// Fetch a record by reference.
     fn get_record(&self, reference: &Reference) -> Result<Option<OwnedRecord>, Error>;
+
+    /// Summarize what the store holds.
+    fn stats(&self) -> Result<StoreStats, Error>;
+
+    /// Totals for the records authored by `author`.
+    fn author_stats(&self, author: &PublicKey) -> Result<RecordTotals, Error> {
+        Ok(self
+            .stats()?
+            .by_author
+            .get(author)
+            .copied()
+            .unwrap_or_default())
+    }
+
+    /// Remove a record, returning true if it was present.
+    fn delete_record(&self, reference: &Reference) -> Result<bool, Error>;
+
+    /// Walk stored records in `order`, returning at most `limit` of them.
+    ///
+    /// Starts at the beginning, or just after the record with id `after`. Pass the
+    /// id of the last entry returned to fetch the next batch; an empty batch means
+    /// the walk is complete. The cursor stays valid if that record is deleted
+    /// in the meantime.
+    fn scan(
+        &self,
+        order: ScanOrder,
+        after: Option<&Id>,
+        limit: usize,
+    ) -> Result<Vec<ScanEntry>, Error>;
+
+    /// Write a consistent snapshot of the store into the directory `path`,
+    /// creating it if needed. Stores that cannot take snapshots return an error.
+    fn backup_to(&self, path: &Path) -> Result<(), Error> {
+        let _ = path;
+        Err(StoreError::new(StoreErrorKind::Other, "this store does not support backups").into())
+    }
+
+    /// Check the indexes the backend keeps beside its records against the
+    /// records themselves, for `check_store`. Returns `None` if the store
+    /// keeps no indexes it can check.
+    fn check_indexes(&self) -> Result<Option<Vec<FsckProblem>>, Error> {
+        Ok(None)
+    }
 }
 
-/// LMDB-backed store adapter using `mosaic-store-lmdb`.
-pub struct LmdbStore(RawLmdbStore);
+/// Order in which `Store::scan` visits records
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ScanOrder {
+    /// Order in which records were inserted into this store
+    Insertion,
+
+    /// Order of the records' timestamps (which is also id order)
+    Timestamp,
+}
+
+/// A record visited by `Store::scan`
+#[derive(Debug, Clone)]
+pub struct ScanEntry {
+    /// The id the store holds the record under
+    pub id: Id,
+
+    /// The record's bytes as stored, not yet verified
+    pub bytes: Vec<u8>,
+}
 
-impl LmdbStore {
-    /// Open or create a LMDB-backed store at `dir`.
-    pub fn open<P: AsRef<Path>>(dir: P, max_size_gb: usize) -> Result<Self, Error> {
-        let inner = RawLmdbStore::new(dir, vec![], max_size_gb).map_err(convert_store_error)?;
-        Ok(Self(inner))
+impl ScanEntry {
+    /// Verify the stored bytes and return them as a record.
+    pub fn record(&self) -> Result<OwnedRecord, Error> {
+        Ok(OwnedRecord::from_vec(self.bytes.clone())?)
     }
 }
 
-impl Store for LmdbStore {
-    fn put_record(&self, record: &Record) -> Result<PutResult, Error> {
-        match self.0.store_record(record) {
-            Ok(_) => Ok(PutResult::Inserted),
-            Err(e) => match e.inner {
-                LmdbInnerError::Duplicate => Ok(PutResult::Duplicate),
-                other => Err(map_store_error(other)),
-            },
-        }
+/// Count and total size of a set of records
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RecordTotals {
+    /// Number of records
+    pub records: u64,
+
+    /// Sum of the records' encoded lengths in bytes
+    pub bytes: u64,
+}
+
+impl RecordTotals {
+    pub(crate) fn add(&mut self, len: u64) {
+        self.records += 1;
+        self.bytes += len;
+    }
+
+    pub(crate) fn remove(&mut self, len: u64) {
+        self.records = self.records.saturating_sub(1);
+        self.bytes = self.bytes.saturating_sub(len);
+    }
+}
+
+/// Statistics about the records held by a `Store`
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct StoreStats {
+    /// Totals over every record
+    pub total: RecordTotals,
+
+    /// Totals broken down by record kind
+    pub by_kind: HashMap<Kind, RecordTotals>,
+
+    /// Totals broken down by author key
+    pub by_author: HashMap<PublicKey, RecordTotals>,
+}
+
+impl StoreStats {
+    /// Account for a record that was added to the store.
+    pub fn record_inserted(&mut self, record: &Record) {
+        let len = record.as_bytes().len() as u64;
+        self.total.add(len);
+        self.by_kind.entry(record.kind()).or_default().add(len);
+        self.by_author
+            .entry(record.author_public_key())
+            .or_default()
+            .add(len);
     }
 
-    fn has_record(&self, reference: &Reference) -> Result<bool, Error> {
-        self.0.has_record(*reference).map_err(convert_store_error)
+    /// Account for a record that was removed from the store.
+    pub fn record_removed(&mut self, record: &Record) {
+        let len = record.as_bytes().len() as u64;
+        self.total.remove(len);
+        remove_from(&mut self.by_kind, record.kind(), len);
+        remove_from(&mut self.by_author, record.author_public_key(), len);
     }
+}
 
-    fn get_record(&self, reference: &Reference) -> Result<Option<OwnedRecord>, Error> {
-        match self.0.get_record_by_ref(*reference) {
-            Ok(Some(record)) => {
-                let bytes = record.as_bytes().to_vec();
-                Ok(Some(OwnedRecord::from_vec(bytes)?))
-            }
-            Ok(None) => Ok(None),
-            Err(e) => Err(convert_store_error(e)),
+fn remove_from<K: std::hash::Hash + Eq>(map: &mut HashMap<K, RecordTotals>, key: K, len: u64) {
+    if let Some(totals) = map.get_mut(&key) {
+        totals.remove(len);
+        if totals.records == 0 {
+            map.remove(&key);
         }
     }
 }
 
-fn convert_store_error(error: mosaic_store_lmdb::Error) -> Error {
-    map_store_error(error.inner)
+/// Classification of a storage backend failure
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StoreErrorKind {
+    /// The store (or the disk beneath it) has no room left
+    OutOfSpace,
+
+    /// An I/O error from the underlying filesystem
+    Io,
+
+    /// Stored data is damaged or could not be decoded
+    Corruption,
+
+    /// A lookup was too broad for the backend to answer
+    FilterTooWide,
+
+    /// A temporary condition; retrying later may succeed
+    Transient,
+
+    /// Anything that does not fit the classes above
+    Other,
 }
 
-fn map_store_error(inner: LmdbInnerError) -> Error {
-    let message = match inner {
-        LmdbInnerError::General(msg) => msg,
-        LmdbInnerError::Io(err) => format!("lmdb io error: {err}"),
-        LmdbInnerError::Lmdb(err) => format!("lmdb backend error: {err}"),
-        LmdbInnerError::BufferTooSmall => "lmdb store buffer too small".to_owned(),
-        LmdbInnerError::EndOfInput => "lmdb store unexpected end of input".to_owned(),
-        LmdbInnerError::FilterTooWide => "lmdb store filter too wide".to_owned(),
-        LmdbInnerError::MosaicCore(err) => format!("lmdb core error: {err}"),
-        LmdbInnerError::Duplicate => "lmdb store duplicate".to_owned(),
-    };
+impl std::fmt::Display for StoreErrorKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            StoreErrorKind::OutOfSpace => write!(f, "out of space"),
+            StoreErrorKind::Io => write!(f, "i/o"),
+            StoreErrorKind::Corruption => write!(f, "corruption"),
+            StoreErrorKind::FilterTooWide => write!(f, "filter too wide"),
+            StoreErrorKind::Transient => write!(f, "transient"),
+            StoreErrorKind::Other => write!(f, "other"),
+        }
+    }
+}
 
-    InnerError::General(message).into_err()
+/// A classified storage backend error, keeping the backend's error as its source
+#[derive(Debug)]
+pub struct StoreError {
+    kind: StoreErrorKind,
+    source: Box<dyn StdError + Send + Sync + 'static>,
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+impl StoreError {
+    /// Create a new `StoreError` of `kind` caused by `source`
+    pub fn new<E>(kind: StoreErrorKind, source: E) -> StoreError
+    where
+        E: Into<Box<dyn StdError + Send + Sync + 'static>>,
+    {
+        StoreError {
+            kind,
+            source: source.into(),
+        }
+    }
 
-    use mosaic_core::{
-        EMPTY_TAG_SET, Kind, OwnedRecord, RecordAddressData, RecordParts, RecordSigningData,
-        SecretKey, Timestamp,
-    };
+    /// The class of this error
+    #[must_use]
+    pub fn kind(&self) -> StoreErrorKind {
+        self.kind
+    }
 
-    fn build_record() -> OwnedRecord {
-        let signing_key = SecretKey::generate();
-        OwnedRecord::new(&RecordParts {
-            signing_data: RecordSigningData::SecretKey(signing_key.clone()),
-            address_data: RecordAddressData::Random(signing_key.public(), Kind::KEY_SCHEDULE),
-            timestamp: Timestamp::now().unwrap(),
-            flags: Default::default(),
-            tag_set: &EMPTY_TAG_SET,
-            payload: b"store test payload",
-        })
-        .unwrap()
+    /// Map the error class to the most accurate Mosaic `ResultCode`.
+    #[must_use]
+    pub fn result_code(&self) -> ResultCode {
+        match self.kind {
+            StoreErrorKind::OutOfSpace | StoreErrorKind::Transient => ResultCode::TemporaryError,
+            StoreErrorKind::Corruption => ResultCode::PersistentError,
+            StoreErrorKind::FilterTooWide => ResultCode::TooOpen,
+            StoreErrorKind::Io | StoreErrorKind::Other => ResultCode::GeneralError,
+        }
     }
+}
 
-    #[test]
-    fn insert_and_detect_duplicate() {
-        let temp_dir = tempfile::tempdir().unwrap();
-        let store = LmdbStore::open(temp_dir.path(), 1).unwrap();
-        let record = build_record();
+impl std::fmt::Display for StoreError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} ({})", self.source, self.kind)
+    }
+}
+
+impl StdError for StoreError {
+    fn source(&self) -> Option<&(dyn StdError + 'static)> {
+        Some(&*self.source)
+    }
+}
 
-        let result = store.put_record(record.as_ref()).unwrap();
-        assert_eq!(result, PutResult::Inserted);
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::test_support::build_record;
 
-        let duplicate = store.put_record(record.as_ref()).unwrap();
-        assert_eq!(duplicate, PutResult::Duplicate);
+    #[test]
+    fn stats_track_inserts_and_removals() {
+        let first = build_record(b"first");
+        let second = build_record(b"second");
+        let mut stats = StoreStats::default();
 
-        let reference = record.id().to_reference();
-        assert!(store.has_record(&reference).unwrap());
+        stats.record_inserted(first.as_ref());
+        stats.record_inserted(second.as_ref());
+        assert_eq!(stats.total.records, 2);
+        assert_eq!(
+            stats.total.bytes,
+            (first.as_bytes().len() + second.as_bytes().len()) as u64
+        );
+        assert_eq!(stats.by_kind[&first.kind()].records, 2);
 
-        let fetched = store.get_record(&reference).unwrap().unwrap();
-        assert_eq!(fetched.as_bytes(), record.as_bytes());
+        stats.record_removed(first.as_ref());
+        assert_eq!(stats.total.records, 1);
+        assert!(!stats.by_author.contains_key(&first.author_public_key()));
+        assert_eq!(stats.by_author[&second.author_public_key()].records, 1);
     }
 }
@@ -0,0 +1,307 @@
+//! Helpers shared by unit tests
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use mosaic_core::{
+    EMPTY_TAG_SET, Id, Kind, OwnedRecord, Record, RecordAddressData, RecordParts,
+    RecordSigningData, Reference, SecretKey, Timestamp,
+};
+
+use crate::{Error, PutResult, ScanEntry, ScanOrder, Store, StoreStats};
+
+/// A `Store` kept entirely in memory
+#[derive(Default)]
+pub(crate) struct InMemoryStore {
+    inner: Mutex<Inner>,
+}
+
+#[derive(Default)]
+struct Inner {
+    records: HashMap<[u8; 48], OwnedRecord>,
+    // Every id ever inserted, in insertion order; deleted ids are left in
+    // place so scan cursors survive deletes.
+    order: Vec<[u8; 48]>,
+}
+
+impl InMemoryStore {
+    pub(crate) fn record_count(&self) -> usize {
+        self.inner.lock().unwrap().records.len()
+    }
+
+    pub(crate) fn contains(&self, id: &[u8; 48]) -> bool {
+        self.inner.lock().unwrap().records.contains_key(id)
+    }
+}
+
+impl Store for InMemoryStore {
+    fn put_record(&self, record: &Record) -> Result<PutResult, Error> {
+        let mut guard = self.inner.lock().unwrap();
+        let id_bytes = *record.id().as_bytes();
+        if guard.records.contains_key(&id_bytes) {
+            Ok(PutResult::Duplicate)
+        } else {
+            let owned = OwnedRecord::from_vec(record.as_bytes().to_vec())?;
+            guard.records.insert(id_bytes, owned);
+            guard.order.push(id_bytes);
+            Ok(PutResult::Inserted)
+        }
+    }
+
+    fn has_record(&self, reference: &Reference) -> Result<bool, Error> {
+        Ok(self.get_record(reference)?.is_some())
+    }
+
+    fn get_record(&self, reference: &Reference) -> Result<Option<OwnedRecord>, Error> {
+        let guard = self.inner.lock().unwrap();
+        if let Some(record) = guard.records.get(reference.as_bytes()) {
+            return Ok(Some(record.clone()));
+        }
+
+        // Address references resolve to the newest version at that address
+        Ok(guard
+            .records
+            .values()
+            .filter(|record| record.address().to_reference().as_bytes() == reference.as_bytes())
+            .max_by_key(|record| record.timestamp())
+            .cloned())
+    }
+
+    fn stats(&self) -> Result<StoreStats, Error> {
+        let mut stats = StoreStats::default();
+        for record in self.inner.lock().unwrap().records.values() {
+            stats.record_inserted(record.as_ref());
+        }
+        Ok(stats)
+    }
+
+    fn delete_record(&self, reference: &Reference) -> Result<bool, Error> {
+        let Some(record) = self.get_record(reference)? else {
+            return Ok(false);
+        };
+        let mut guard = self.inner.lock().unwrap();
+        Ok(guard.records.remove(record.id().as_bytes()).is_some())
+    }
+
+    fn scan(
+        &self,
+        order: ScanOrder,
+        after: Option<&Id>,
+        limit: usize,
+    ) -> Result<Vec<ScanEntry>, Error> {
+        let guard = self.inner.lock().unwrap();
+        let ids: Vec<[u8; 48]> = match order {
+            ScanOrder::Insertion => {
+                let start = match after {
+                    Some(id) => match guard.order.iter().rposition(|o| o == id.as_bytes()) {
+                        Some(position) => position + 1,
+                        None => return Err("scan cursor is not a known record id".into()),
+                    },
+                    None => 0,
+                };
+                guard.order[start..].to_vec()
+            }
+            ScanOrder::Timestamp => {
+                let mut ids: Vec<[u8; 48]> = guard.records.keys().copied().collect();
+                ids.sort_unstable();
+                ids.retain(|id| after.is_none_or(|after| id > after.as_bytes()));
+                ids
+            }
+        };
+
+        Ok(ids
+            .iter()
+            .filter_map(|id| guard.records.get(id))
+            .take(limit)
+            .map(|record| ScanEntry {
+                id: record.id(),
+                bytes: record.as_bytes().to_vec(),
+            })
+            .collect())
+    }
+}
+
+/// Build a fresh, validly signed record with the given payload
+pub(crate) fn build_record(payload: &[u8]) -> OwnedRecord {
+    build_record_with(
+        &SecretKey::generate(),
+        Kind::KEY_SCHEDULE,
+        Timestamp::now().unwrap(),
+        payload,
+    )
+}
+
+/// Build a record signed by `signing_key` with the given kind and timestamp
+pub(crate) fn build_record_with(
+    signing_key: &SecretKey,
+    kind: Kind,
+    timestamp: Timestamp,
+    payload: &[u8],
+) -> OwnedRecord {
+    OwnedRecord::new(&RecordParts {
+        signing_data: RecordSigningData::SecretKey(signing_key.clone()),
+        address_data: RecordAddressData::Random(signing_key.public(), kind),
+        timestamp,
+        flags: Default::default(),
+        tag_set: &EMPTY_TAG_SET,
+        payload,
+    })
+    .unwrap()
+}
+
+/// A timestamp `ago` before now
+pub(crate) fn timestamp_ago(ago: std::time::Duration) -> Timestamp {
+    let now = Timestamp::now().unwrap().as_nanoseconds();
+    Timestamp::from_nanoseconds(now - ago.as_nanos() as i64).unwrap()
+}
+
+/// Behaviour every `Store` backend must share, run by each backend's tests
+pub(crate) mod conformance {
+    use mosaic_core::{Id, Kind, OwnedRecord};
+
+    use super::build_record;
+    use crate::{PutResult, RecordTotals, ScanOrder, Store};
+
+    /// Run every check, each against a fresh empty store from `open`
+    pub(crate) fn check_store<S: Store>(mut open: impl FnMut() -> S) {
+        insert_and_detect_duplicate(&open());
+        stats_are_maintained(&open());
+        delete_and_scan(&open());
+        cursor_survives_deleting_the_newest(&open());
+    }
+
+    /// Check that statistics survive a reopen; `open` must reopen the same
+    /// initially empty store each time
+    pub(crate) fn check_stats_persist<S: Store>(mut open: impl FnMut() -> S) {
+        let first = build_record(b"first");
+        let second = build_record(b"second");
+        let bytes = (first.as_bytes().len() + second.as_bytes().len()) as u64;
+
+        {
+            let store = open();
+            store.put_record(first.as_ref()).unwrap();
+            store.put_record(second.as_ref()).unwrap();
+        }
+
+        let reopened = open();
+        let stats = reopened.stats().unwrap();
+        assert_eq!(stats.total, RecordTotals { records: 2, bytes });
+        assert_eq!(stats.by_author[&first.author_public_key()].records, 1);
+    }
+
+    fn insert_and_detect_duplicate(store: &dyn Store) {
+        let record = build_record(b"store test payload");
+        assert_eq!(
+            store.put_record(record.as_ref()).unwrap(),
+            PutResult::Inserted
+        );
+        assert_eq!(
+            store.put_record(record.as_ref()).unwrap(),
+            PutResult::Duplicate
+        );
+
+        let reference = record.id().to_reference();
+        assert!(store.has_record(&reference).unwrap());
+
+        let fetched = store.get_record(&reference).unwrap().unwrap();
+        assert_eq!(fetched.as_bytes(), record.as_bytes());
+
+        let by_address = store
+            .get_record(&record.address().to_reference())
+            .unwrap()
+            .unwrap();
+        assert_eq!(by_address.as_bytes(), record.as_bytes());
+    }
+
+    fn stats_are_maintained(store: &dyn Store) {
+        let first = build_record(b"first");
+        let second = build_record(b"second");
+        let bytes = (first.as_bytes().len() + second.as_bytes().len()) as u64;
+
+        store.put_record(first.as_ref()).unwrap();
+        store.put_record(second.as_ref()).unwrap();
+        store.put_record(first.as_ref()).unwrap();
+
+        let stats = store.stats().unwrap();
+        assert_eq!(stats.total, RecordTotals { records: 2, bytes });
+        assert_eq!(stats.by_kind[&Kind::KEY_SCHEDULE].records, 2);
+        assert_eq!(stats.by_author.len(), 2);
+        assert_eq!(
+            store
+                .author_stats(&first.author_public_key())
+                .unwrap()
+                .records,
+            1
+        );
+
+        store.delete_record(&first.id().to_reference()).unwrap();
+        let stats = store.stats().unwrap();
+        assert_eq!(stats.total.records, 1);
+        assert!(!stats.by_author.contains_key(&first.author_public_key()));
+    }
+
+    fn delete_and_scan(store: &dyn Store) {
+        let records: Vec<OwnedRecord> = (0..5)
+            .map(|i| build_record(format!("record {i}").as_bytes()))
+            .collect();
+        for record in &records {
+            store.put_record(record.as_ref()).unwrap();
+        }
+
+        let first_page = store.scan(ScanOrder::Insertion, None, 2).unwrap();
+        assert_eq!(first_page.len(), 2);
+        assert_eq!(first_page[0].id, records[0].id());
+        assert_eq!(first_page[1].bytes, records[1].as_bytes());
+
+        // Deleting the cursor record must not break the walk.
+        let cursor = first_page[1].id;
+        assert!(store.delete_record(&cursor.to_reference()).unwrap());
+        assert!(!store.delete_record(&cursor.to_reference()).unwrap());
+        assert!(!store.has_record(&cursor.to_reference()).unwrap());
+
+        let rest = store.scan(ScanOrder::Insertion, Some(&cursor), 10).unwrap();
+        let rest_ids: Vec<Id> = rest.iter().map(|entry| entry.id).collect();
+        let expected: Vec<Id> = records[2..].iter().map(|r| r.id()).collect();
+        assert_eq!(rest_ids, expected);
+
+        let by_time = store.scan(ScanOrder::Timestamp, None, 10).unwrap();
+        assert_eq!(by_time.len(), 4);
+        assert!(
+            by_time
+                .windows(2)
+                .all(|w| w[0].id.as_bytes() <= w[1].id.as_bytes())
+        );
+        assert!(by_time.iter().all(|entry| entry.id != cursor));
+
+        assert_eq!(store.stats().unwrap().total.records, 4);
+    }
+
+    fn cursor_survives_deleting_the_newest(store: &dyn Store) {
+        let oldest = build_record(b"oldest");
+        let newest = build_record(b"newest");
+        store.put_record(oldest.as_ref()).unwrap();
+        store.put_record(newest.as_ref()).unwrap();
+
+        // A walk that stopped at the newest record resumes from there after it
+        // is deleted, and still finds everything inserted since.
+        assert!(store.delete_record(&newest.id().to_reference()).unwrap());
+        let later = build_record(b"later");
+        store.put_record(later.as_ref()).unwrap();
+
+        let rest = store
+            .scan(ScanOrder::Insertion, Some(&newest.id()), 10)
+            .unwrap();
+        let rest_ids: Vec<Id> = rest.iter().map(|entry| entry.id).collect();
+        assert_eq!(rest_ids, vec![later.id()]);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn in_memory_store_conforms() {
+        conformance::check_store(InMemoryStore::default);
+    }
+}
@@ -0,0 +1,170 @@
+use std::fmt;
+use std::future::Future;
+use std::time::Duration;
+
+use mosaic_core::ResultCode;
+use tokio::time::Instant;
+
+/// Limits on how long a connection may take or stay quiet. `None` disables
+/// that timeout.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Timeouts {
+    /// Time from connecting to completing HELLO / HELLO ACK
+    pub handshake: Option<Duration>,
+
+    /// Longest wait for the next channel once the handshake is done
+    pub idle: Option<Duration>,
+
+    /// Longest time to receive a whole message once a channel is open
+    pub message: Option<Duration>,
+}
+
+impl Default for Timeouts {
+    fn default() -> Timeouts {
+        Timeouts {
+            handshake: Some(Duration::from_secs(10)),
+            idle: Some(Duration::from_secs(5 * 60)),
+            message: Some(Duration::from_secs(30)),
+        }
+    }
+}
+
+/// Which timeout closed a connection
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TimeoutKind {
+    Handshake,
+    Idle,
+    Message,
+}
+
+impl TimeoutKind {
+    /// Result code the connection is closed with
+    #[must_use]
+    pub fn result_code(&self) -> ResultCode {
+        match self {
+            // Not saying HELLO is a protocol violation
+            TimeoutKind::Handshake => ResultCode::Invalid,
+            // Nothing went wrong; the peer just has nothing more to say
+            TimeoutKind::Idle => ResultCode::Success,
+            // The peer may have a slow link and can try again
+            TimeoutKind::Message => ResultCode::TemporaryError,
+        }
+    }
+
+    /// Reason given to the peer when its connection is closed
+    #[must_use]
+    pub fn reason(&self) -> &'static [u8] {
+        match self {
+            TimeoutKind::Handshake => b"handshake timed out",
+            TimeoutKind::Idle => b"idle timeout",
+            TimeoutKind::Message => b"message timed out",
+        }
+    }
+}
+
+impl fmt::Display for TimeoutKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&String::from_utf8_lossy(self.reason()))
+    }
+}
+
+impl Timeouts {
+    /// When waiting for the next channel must give up, and why
+    pub(crate) fn channel_deadline(
+        &self,
+        connected_at: Instant,
+        handshaken: bool,
+        now: Instant,
+    ) -> Option<(Instant, TimeoutKind)> {
+        if handshaken {
+            self.idle.map(|idle| (now + idle, TimeoutKind::Idle))
+        } else {
+            self.handshake_deadline(connected_at)
+        }
+    }
+
+    /// When receiving a message on an open channel must give up, and why
+    pub(crate) fn message_deadline(
+        &self,
+        connected_at: Instant,
+        handshaken: bool,
+        now: Instant,
+    ) -> Option<(Instant, TimeoutKind)> {
+        let message = self
+            .message
+            .map(|message| (now + message, TimeoutKind::Message));
+        let handshake = if handshaken {
+            None
+        } else {
+            self.handshake_deadline(connected_at)
+        };
+        match (message, handshake) {
+            (Some(a), Some(b)) => Some(if b.0 <= a.0 { b } else { a }),
+            (a, b) => a.or(b),
+        }
+    }
+
+    fn handshake_deadline(&self, connected_at: Instant) -> Option<(Instant, TimeoutKind)> {
+        self.handshake
+            .map(|handshake| (connected_at + handshake, TimeoutKind::Handshake))
+    }
+}
+
+/// Run `future` until `deadline`, returning the kind of timeout that expired
+/// instead if it does not finish in time
+pub(crate) async fn within<T>(
+    deadline: Option<(Instant, TimeoutKind)>,
+    future: impl Future<Output = T>,
+) -> Result<T, TimeoutKind> {
+    match deadline {
+        Some((at, kind)) => tokio::time::timeout_at(at, future).await.map_err(|_| kind),
+        None => Ok(future.await),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SECOND: Duration = Duration::from_secs(1);
+
+    #[test]
+    fn handshake_deadline_applies_until_hello() {
+        let timeouts = Timeouts {
+            handshake: Some(10 * SECOND),
+            idle: Some(60 * SECOND),
+            message: Some(30 * SECOND),
+        };
+        let connected_at = Instant::now();
+        let now = connected_at + 5 * SECOND;
+
+        assert_eq!(
+            timeouts.channel_deadline(connected_at, false, now),
+            Some((connected_at + 10 * SECOND, TimeoutKind::Handshake))
+        );
+        assert_eq!(
+            timeouts.message_deadline(connected_at, false, now),
+            Some((connected_at + 10 * SECOND, TimeoutKind::Handshake))
+        );
+
+        assert_eq!(
+            timeouts.channel_deadline(connected_at, true, now),
+            Some((now + 60 * SECOND, TimeoutKind::Idle))
+        );
+        assert_eq!(
+            timeouts.message_deadline(connected_at, true, now),
+            Some((now + 30 * SECOND, TimeoutKind::Message))
+        );
+    }
+
+    #[tokio::test]
+    async fn slow_futures_time_out() {
+        let soon = Some((
+            Instant::now() + Duration::from_millis(10),
+            TimeoutKind::Idle,
+        ));
+        let result = within(soon, tokio::time::sleep(SECOND)).await;
+        assert_eq!(result, Err(TimeoutKind::Idle));
+        assert_eq!(within(None, async { 7 }).await, Ok(7));
+    }
+}
@@ -1,4 +1,5 @@
 use crate::client::ClientData;
+use crate::{ProofOfWork, work_bits};
 
 use mosaic_core::{Error as CoreError, InnerError, Message, MessageType, OwnedRecord, ResultCode};
 
@@ -11,6 +12,8 @@ pub enum SubmissionValidationError {
     WrongMessageType,
     /// Record failed structural or cryptographic verification.
     RecordInvalid(CoreError),
+    /// Record id has fewer leading zero bits than the proof-of-work policy requires.
+    InsufficientWork { required: u8, found: u32 },
 }
 
 impl SubmissionValidationError {
@@ -24,6 +27,7 @@ impl SubmissionValidationError {
                 InnerError::RecordTooLong => ResultCode::TooLarge,
                 _ => ResultCode::Invalid,
             },
+            SubmissionValidationError::InsufficientWork { .. } => ProofOfWork::RESULT_CODE,
         }
     }
 }
@@ -31,9 +35,12 @@ impl SubmissionValidationError {
 /// Validate a `Submission` message according to the Mosaic specification.
 ///
 /// Returns an owned, verified record on success so downstream code can persist it.
+/// With a proof-of-work policy, the record id must also carry enough work,
+/// unless the client is an admin.
 pub fn validate_submission(
     message: &Message,
     client: &ClientData,
+    pow: Option<&ProofOfWork>,
 ) -> Result<OwnedRecord, SubmissionValidationError> {
     if message.message_type() != MessageType::Submission {
         return Err(SubmissionValidationError::WrongMessageType);
@@ -44,7 +51,18 @@ pub fn validate_submission(
     }
 
     let record_bytes = &message.as_bytes()[8..];
-    OwnedRecord::from_vec(record_bytes.to_vec()).map_err(SubmissionValidationError::RecordInvalid)
+    let record = OwnedRecord::from_vec(record_bytes.to_vec())
+        .map_err(SubmissionValidationError::RecordInvalid)?;
+
+    if let Some(pow) = pow.filter(|_| !client.role.is_admin()) {
+        let required = pow.required_bits(client.peer, &record.author_public_key());
+        let found = work_bits(&record.id());
+        if found < u32::from(required) {
+            return Err(SubmissionValidationError::InsufficientWork { required, found });
+        }
+    }
+
+    Ok(record)
 }
 
 #[cfg(test)]
@@ -53,6 +71,8 @@ mod tests {
 
     use std::net::{IpAddr, Ipv4Addr, SocketAddr};
 
+    use crate::Role;
+
     use mosaic_core::{
         EMPTY_TAG_SET, Kind, OwnedRecord, RecordAddressData, RecordParts, RecordSigningData,
         SecretKey, Timestamp,
@@ -70,6 +90,7 @@ mod tests {
                 mosaic_version: Some(0),
                 applications: Some(vec![0]),
                 closing_result: None,
+                role: Role::ReadWrite,
             }
         } else {
             ClientData {
@@ -78,30 +99,43 @@ mod tests {
                 mosaic_version: None,
                 applications: None,
                 closing_result: None,
+                role: Role::ReadWrite,
             }
         }
     }
 
     fn build_record() -> OwnedRecord {
-        let signing_key = SecretKey::generate();
+        build_record_by(&SecretKey::generate(), b"hello world")
+    }
+
+    fn build_record_by(signing_key: &SecretKey, payload: &[u8]) -> OwnedRecord {
         OwnedRecord::new(&RecordParts {
             signing_data: RecordSigningData::SecretKey(signing_key.clone()),
             address_data: RecordAddressData::Random(signing_key.public(), Kind::KEY_SCHEDULE),
             timestamp: Timestamp::now().unwrap(),
             flags: Default::default(),
             tag_set: &EMPTY_TAG_SET,
-            payload: b"hello world",
+            payload,
         })
         .unwrap()
     }
 
+    /// Vary the payload until the record id has at least (or, with `enough`
+    /// false, fewer than) `bits` of work
+    fn mine(signing_key: &SecretKey, bits: u32, enough: bool) -> OwnedRecord {
+        (0_u64..)
+            .map(|nonce| build_record_by(signing_key, &nonce.to_le_bytes()))
+            .find(|record| (work_bits(&record.id()) >= bits) == enough)
+            .unwrap()
+    }
+
     #[test]
     fn submission_requires_handshake() {
         let client = make_client(false);
         let record = build_record();
         let message = mosaic_core::Message::new_submission(&record).unwrap();
 
-        let err = validate_submission(&message, &client).unwrap_err();
+        let err = validate_submission(&message, &client, None).unwrap_err();
         assert!(matches!(
             err,
             SubmissionValidationError::HandshakeNotComplete
@@ -114,7 +148,7 @@ mod tests {
         let client = make_client(true);
         let message = mosaic_core::Message::new_hello(0, &[]).unwrap();
 
-        let err = validate_submission(&message, &client).unwrap_err();
+        let err = validate_submission(&message, &client, None).unwrap_err();
         assert!(matches!(err, SubmissionValidationError::WrongMessageType));
         assert_eq!(err.result_code(), ResultCode::Invalid);
     }
@@ -125,7 +159,7 @@ mod tests {
         let record = build_record();
         let message = mosaic_core::Message::new_submission(&record).unwrap();
 
-        let validated = validate_submission(&message, &client).unwrap();
+        let validated = validate_submission(&message, &client, None).unwrap();
         assert_eq!(validated.as_bytes(), record.as_bytes());
     }
 
@@ -140,11 +174,42 @@ mod tests {
         corrupted[8] ^= 0xFF;
         let invalid_message = unsafe { mosaic_core::Message::from_bytes_unchecked(corrupted) };
 
-        let err = validate_submission(&invalid_message, &client).unwrap_err();
+        let err = validate_submission(&invalid_message, &client, None).unwrap_err();
         assert!(matches!(err, SubmissionValidationError::RecordInvalid(_)));
         assert_eq!(err.result_code(), ResultCode::Invalid);
     }
 
+    #[test]
+    fn proof_of_work_is_required_unless_exempt() {
+        let exempt_key = SecretKey::generate();
+        let pow = ProofOfWork::new(4, [exempt_key.public()]);
+        let mut client = make_client(true);
+
+        let worked = Message::new_submission(&mine(&SecretKey::generate(), 4, true)).unwrap();
+        assert!(validate_submission(&worked, &client, Some(&pow)).is_ok());
+
+        let lazy = Message::new_submission(&mine(&exempt_key, 4, false)).unwrap();
+        let err = validate_submission(&lazy, &client, Some(&pow)).unwrap_err();
+        assert!(matches!(
+            err,
+            SubmissionValidationError::InsufficientWork { required: 4, .. }
+        ));
+        assert_eq!(err.result_code(), ProofOfWork::RESULT_CODE);
+        assert_ne!(err.result_code(), crate::RateLimitConfig::RESULT_CODE);
+
+        // Exempt authors need no work, but only from an authenticated peer
+        client.peer = Some(SecretKey::generate().public());
+        assert!(validate_submission(&lazy, &client, Some(&pow)).is_ok());
+
+        let stranger = Message::new_submission(&mine(&SecretKey::generate(), 4, false)).unwrap();
+        assert!(validate_submission(&stranger, &client, Some(&pow)).is_err());
+        client.role = Role::Admin;
+        assert!(validate_submission(&stranger, &client, Some(&pow)).is_ok());
+        client.role = Role::ReadWrite;
+        pow.set_difficulty(0);
+        assert!(validate_submission(&stranger, &client, Some(&pow)).is_ok());
+    }
+
     #[test]
     fn record_too_long_maps_to_toolarge() {
         let err = SubmissionValidationError::RecordInvalid(InnerError::RecordTooLong.into_err());
@@ -60,6 +60,19 @@ async fn publish_smoke_quic_lmdb() -> Result<(), Box<dyn std::error::Error>> {
         approver: AlwaysAllowedApprover,
         logger: logger.clone(),
         store: Arc::clone(&store),
+        retention: None,
+        quotas: None,
+        duplicate_filter: None,
+        backups: None,
+        rate_limits: None,
+        connection_limits: None,
+        timeouts: Default::default(),
+        peer_authorizer: None,
+        proof_of_work: None,
+        blocklist: None,
+        moderation: None,
+        read_access: None,
+        read_members: None,
     };
 
     let server = Server::new(server_config)?;